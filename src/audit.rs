@@ -0,0 +1,120 @@
+//! The audit log, a machine readable record of every URI handled.
+//!
+//! Each invocation is appended to the audit log as one JSON object per line.
+//! The URIs, the commandline, the details of the decisions and the error are
+//! redacted as configured in `redaction`.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use resolve_path::PathResolveExt;
+use serde::Serialize;
+use time::format_description::well_known::Rfc3339;
+
+use crate::config::Config;
+use crate::error::ProtoHandlerError;
+use crate::redact::redact;
+use crate::runner::{Invocation, PolicyCheck};
+
+const AUDIT_FILE: &str = "audit.jsonl";
+
+/// One line of the audit log
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct AuditRecord {
+    /// When the URI started being handled, in RFC 3339 format
+    pub timestamp : String,
+    /// The unique id of the invocation
+    pub id : String,
+    /// The redacted URI
+    pub uri : String,
+    /// The protocol found in the URI
+    pub protocol : Option<String>,
+    /// The route within the protocol that handled the URI
+    pub route : Option<String>,
+    /// The redacted URIs the invocation was forwarded to, in order
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub forwards : Vec<String>,
+    /// The redacted program and arguments of the command
+    pub argv : Vec<String>,
+    /// The policies applied before running the command, with redacted details
+    pub decisions : Vec<PolicyCheck>,
+    /// How many times the handler was run
+    pub attempts : u32,
+    /// True if the command ran and exited successfully
+    pub success : bool,
+    /// The exit code of the command, if it ran to completion
+    pub exit_code : Option<i32>,
+    /// The redacted error that stopped the URI from being handled
+    pub error : Option<String>,
+    /// How long handling the URI took, in milliseconds
+    pub duration_ms : u128,
+    /// The process id of the command
+    pub pid : Option<u32>,
+}
+
+impl AuditRecord {
+    /// Creates the audit record of an invocation, redacting it as configured.
+    #[must_use] pub fn new(invocation : &Invocation, config : &Config) -> Self {
+        Self {
+            timestamp : invocation.started.format(&Rfc3339).unwrap_or_default(),
+            id : invocation.id.clone(),
+            uri : redact(&invocation.uri, &config.redaction),
+            protocol : invocation.protocol.clone(),
+            route : invocation.route.clone(),
+            forwards : invocation.forwards.iter().map(|u| redact(u, &config.redaction)).collect(),
+            argv : invocation.argv.iter().map(|a| redact(a, &config.redaction)).collect(),
+            decisions : invocation
+                .decisions
+                .iter()
+                .map(|d| PolicyCheck { detail : redact(&d.detail, &config.redaction), ..d.clone() })
+                .collect(),
+            attempts : invocation.attempts,
+            success : invocation.success(),
+            exit_code : invocation.exit_code(),
+            error : invocation.error().map(|e| redact(&e, &config.redaction)),
+            duration_ms : invocation.duration.as_millis(),
+            pid : invocation.pid,
+        }
+    }
+}
+
+/// Returns the path of the audit log
+#[must_use] pub fn audit_path(config : &Config) -> PathBuf {
+    match &config.audit.path {
+        Some(path) => path.resolve().into_owned(),
+        None => config.get_state_directory().join(AUDIT_FILE),
+    }
+}
+
+/// Appends the record of an invocation to the audit log, if it is enabled.
+///
+/// # Errors
+///
+/// This function will return an error if the audit log cannot be written.
+pub fn record(invocation : &Invocation, config : &Config) -> Result<(), ProtoHandlerError> {
+    if !config.audit.enabled {
+        return Ok(());
+    }
+    let path = audit_path(config);
+    let err = |e : std::io::Error| ProtoHandlerError::AuditError {
+        path : path.display().to_string(),
+        reason : e.to_string(),
+    };
+
+    let mut line = serde_json::to_string(&AuditRecord::new(invocation, config))
+        .map_err(|e| err(std::io::Error::other(e)))?;
+    line.push('\n');
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(err)?;
+    }
+    // A single write to a file opened for appending keeps concurrent records
+    // from interleaving
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| f.write_all(line.as_bytes()))
+        .map_err(err)
+}
@@ -1,4 +1,11 @@
-use clap::Parser;
+use std::path::PathBuf;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use clap_complete::{ArgValueCandidates, CompletionCandidate, Shell};
+
+use protohandlers::config::{Config, UriParser};
+use protohandlers::native::Browser;
+use protohandlers::runner::CATCH_ALL;
 
 // URI = scheme ":" ["//" authority] path ["?" query] ["#" fragment]
 // URI = proto :// subcommand ? payload
@@ -14,44 +21,419 @@ use clap::Parser;
 //    - title = 'Get started'
 //    - body = 'get started'
 
+/// The environment variable asking for completions, see `CompleteEnv`
+pub(crate) const COMPLETE_VAR : &str = "COMPLETE";
+
 #[derive(Debug, Parser)]
-#[command(version)]
+#[command(
+    version,
+    about,
+    arg_required_else_help = true,
+    subcommand_negates_reqs = true,
+    override_usage = "protohandlers [OPTIONS] <URI>\n       protohandlers [OPTIONS] <COMMAND>"
+)]
 pub(crate) struct Cli {
-    /// Register a new protocol
-    ///
-    /// Add a protocol that will be handled by protoHandle.rs
-    #[arg(
-        group = "registration",
-        requires = "script_path",
-        short = 'r',
-        long = "register-protocol"
-    )]
-    pub new_proto : Option<String>,
+    #[command(subcommand)]
+    pub command : Option<Command>,
 
-    /// The path to the script to run
+    /// The URIs to be processed.
     ///
-    /// The script to associate with the given protocol.  Path can be given as
-    /// relative to 'script-directory' or as a fully-qualified path
-    #[arg(group = "registration", short = 's', long = "script-path")]
-    pub script_path : Option<String>,
+    /// Giving the URIs without a subcommand is the same as `open <URI>...`, so
+    /// that protoHandler can be called as `protohandlers %u` or
+    /// `protohandlers %U`
+    #[arg(value_name = "URI", required_unless_present = "uri_flag", add = ArgValueCandidates::new(|| uri_candidates(&completed_config())))]
+    pub uris : Vec<String>,
 
-    /// The URI to be processed.
-    ///
-    /// The URI to process by protoHandle.rs.  Which script or process is
-    /// determined by the URI protocol-scheme
-    #[arg(group = "input", short = 'u', long = "uri")]
-    pub uri : Option<String>,
+    /// The URI to be processed (deprecated, use `open <URI>`)
+    #[arg(short = 'u', long = "uri", hide = true, conflicts_with = "uris")]
+    pub uri_flag : Option<String>,
 
     /// Alternate configuration file to use
     ///
     /// Use an alternate configuration file instead of the default
-    #[arg(short = 'c', long = "config-file")]
+    #[arg(short = 'c', long = "config-file", global = true)]
     pub config_file : Option<String>,
 
     /// Alternate log file to write to
     ///
     /// Depending on the verbosity settings, send log messages to an alternate
     /// file, instead of the default.
-    #[arg(short = 'l', long = "log-file")]
+    #[arg(short = 'l', long = "log-file", global = true)]
     pub log_file : Option<String>,
 }
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum Command {
+    /// Process one or more URIs
+    ///
+    /// Run the script configured for each URI protocol-scheme.  When several
+    /// URIs are given, they are processed as configured in `batch.mode` and a
+    /// summary of the results is printed
+    Open {
+        /// The URIs to be processed.
+        ///
+        /// The URIs to process by protoHandle.rs.  Which script or process is
+        /// determined by the URI protocol-scheme
+        #[arg(required = true, add = ArgValueCandidates::new(|| uri_candidates(&completed_config())))]
+        uris : Vec<String>,
+
+        /// Explain how the URIs would be handled, without running anything
+        #[arg(long = "dry-run")]
+        dry_run : bool,
+
+        /// The format of the explanation or of the summary
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format : OutputFormat,
+    },
+
+    /// Register a new protocol
+    ///
+    /// Add a protocol that will be handled by protoHandle.rs to the
+    /// configuration file
+    Register(RegisterArgs),
+
+    /// Unregister a protocol
+    ///
+    /// Remove the protocol from the configuration file
+    Unregister {
+        /// The name of the protocol to remove
+        #[arg(add = ArgValueCandidates::new(|| protocol_candidates(&completed_config())))]
+        name : String,
+    },
+
+    /// List the configured protocols
+    List {
+        /// The format of the output
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format : OutputFormat,
+    },
+
+    /// Check the configuration for problems
+    ///
+    /// Verify that every protocol refers to a configured shell, and that the
+    /// shell programs and scripts exist
+    Check {
+        /// The format of the output
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format : OutputFormat,
+    },
+
+    /// Explain how a URI would be handled, without running anything
+    ///
+    /// Show the components of the URI, the protocol that matches it, the shell,
+    /// script and commandline that would be run, and the checks that would
+    /// pass or fail.
+    #[command(visible_alias = "dry-run")]
+    Explain {
+        /// The URI to explain
+        #[arg(add = ArgValueCandidates::new(|| uri_candidates(&completed_config())))]
+        uri : String,
+
+        /// The format of the output
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format : OutputFormat,
+    },
+
+    /// Inspect the configuration
+    #[command(subcommand)]
+    Config(ConfigCommand),
+
+    /// Inspect the log file
+    #[command(subcommand)]
+    Log(LogCommand),
+
+    /// Inspect and replay the invocation history
+    #[command(subcommand)]
+    History(HistoryCommand),
+
+    /// Inspect, retry or purge the dead-letter queue
+    ///
+    /// The URIs of the protocols with `retry.dead_letter` are queued when
+    /// every attempt failed
+    #[command(subcommand)]
+    Queue(QueueCommand),
+
+    /// Act as the native-messaging host of a browser extension
+    ///
+    /// A browser extension can send URIs to protoHandler directly, instead of
+    /// opening a custom scheme the browser may block
+    #[command(subcommand)]
+    Native(NativeCommand),
+
+    /// Run or control the resident daemon
+    ///
+    /// The daemon keeps the configuration loaded and handles the URIs sent to
+    /// it on a Unix socket.  When `daemon.enabled` is true, `open` sends the
+    /// URIs to the daemon if it is running, and handles them itself otherwise
+    #[command(subcommand)]
+    Daemon(DaemonCommand),
+
+    /// Generate a shell completion script
+    ///
+    /// The script calls protoHandler back while completing, so that the names
+    /// of the protocols and shells are read from the configuration file.
+    /// Source it when the shell starts, so that it matches the installed
+    /// version
+    Completions {
+        /// The shell to generate the script for
+        #[arg(value_enum)]
+        shell : Shell,
+    },
+
+    /// Generate the manual page
+    ///
+    /// Print the manual page in roff format, or write a page for each
+    /// subcommand to a directory
+    Man {
+        /// Write the manual pages to this directory instead
+        #[arg(long = "out-dir")]
+        out_dir : Option<PathBuf>,
+    },
+}
+
+#[derive(Debug, Args)]
+pub(crate) struct RegisterArgs {
+    /// The name of the protocol
+    pub name : String,
+
+    /// The path to the script to run
+    ///
+    /// The script to associate with the given protocol.  Path can be given as
+    /// relative to 'script-directory' or as a fully-qualified path
+    #[arg(short = 's', long = "script-path")]
+    pub script_path : String,
+
+    /// The shell used to run the script
+    #[arg(long = "shell", default_value = "pwsh", add = ArgValueCandidates::new(|| shell_candidates(&completed_config())))]
+    pub shell : String,
+
+    /// A short description of the protocol
+    #[arg(short = 'd', long = "desc", default_value = "")]
+    pub desc : String,
+
+    /// Arguments placed after the script, before the URI
+    #[arg(long = "script-arg", allow_hyphen_values = true)]
+    pub script_args : Vec<String>,
+
+    /// Replace the protocol if it is already registered
+    #[arg(short = 'f', long = "force")]
+    pub force : bool,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum ConfigCommand {
+    /// Print the path of the configuration file
+    Path,
+
+    /// Print the configuration in use
+    Show,
+
+    /// Print the JSON Schema of the configuration file
+    ///
+    /// Output the JSON Schema describing the configuration file, so that
+    /// editors can validate and complete it.
+    Schema,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum LogCommand {
+    /// Print the path of the log file
+    Path,
+
+    /// Print the end of the log file
+    Show {
+        /// The number of lines to print
+        #[arg(short = 'n', long = "lines", default_value_t = 20)]
+        lines : usize,
+    },
+}
+
+/// Returns the configuration read from the default file, to complete the
+/// names it configures.  The configuration is empty if it cannot be read.
+fn completed_config() -> Config {
+    let mut config = Config::new();
+    let file = config.get_file();
+    if file.exists() && config.load(file.display().to_string()).is_err() {
+        return Config::new();
+    }
+    config
+}
+
+/// Returns the names of the configured protocols
+pub(crate) fn protocol_candidates(config : &Config) -> Vec<CompletionCandidate> {
+    config
+        .protocols
+        .iter()
+        .map(|p| CompletionCandidate::new(&p.name).help(Some(p.desc.clone().into())))
+        .collect()
+}
+
+/// Returns the names of the configured shells
+fn shell_candidates(config : &Config) -> Vec<CompletionCandidate> {
+    config.shells.iter().map(|s| CompletionCandidate::new(&s.name)).collect()
+}
+
+/// Returns the beginning of the URIs of the configured protocols
+pub(crate) fn uri_candidates(config : &Config) -> Vec<CompletionCandidate> {
+    config
+        .protocols
+        .iter()
+        .filter(|p| p.name != CATCH_ALL)
+        .map(|p| {
+            let separator = match p.parser {
+                UriParser::Mailto | UriParser::Magnet | UriParser::Tel => ":",
+                _ => "://",
+            };
+            CompletionCandidate::new(format!("{}{separator}", p.name)).help(Some(p.desc.clone().into()))
+        })
+        .collect()
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum DaemonCommand {
+    /// Run the daemon in the foreground, until it is stopped
+    Run,
+
+    /// Print whether the daemon is running
+    Status,
+
+    /// Stop the daemon once the URIs it is handling are done
+    Stop,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum HistoryCommand {
+    /// List the recent invocations, newest first
+    List {
+        /// Only list the invocations of this protocol
+        #[arg(short = 'p', long = "protocol", add = ArgValueCandidates::new(|| protocol_candidates(&completed_config())))]
+        protocol : Option<String>,
+
+        /// Only list the invocations that failed
+        #[arg(long = "failed")]
+        failed : bool,
+
+        /// The number of invocations to list
+        #[arg(short = 'n', long = "limit", default_value_t = 20)]
+        limit : usize,
+
+        /// The format of the output
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format : OutputFormat,
+    },
+
+    /// Show the details of an invocation
+    Show {
+        /// The id of the invocation, or the beginning of it
+        id : String,
+
+        /// The format of the output
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format : OutputFormat,
+    },
+
+    /// Run a past invocation again
+    Replay {
+        /// The id of the invocation, or the beginning of it
+        id : String,
+
+        /// Run the invocation with the protocols of this configuration file
+        /// instead
+        #[arg(long = "with-config")]
+        with_config : Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum NativeCommand {
+    /// Handle the messages of the browser on stdin, replying on stdout
+    ///
+    /// This is started by the browser, through the script written by
+    /// `native manifest`
+    Host {
+        /// The arguments given by the browser, such as the origin of the
+        /// extension
+        #[arg(allow_hyphen_values = true, trailing_var_arg = true)]
+        args : Vec<String>,
+    },
+
+    /// Install the native-messaging host manifest for the browsers
+    ///
+    /// Write the manifest in the directory of the user where each browser
+    /// looks for it, and the script the browser starts
+    Manifest {
+        /// The id of an extension allowed to use the host
+        #[arg(short = 'e', long = "extension", required = true)]
+        extensions : Vec<String>,
+
+        /// The browsers to install the manifest for, every browser by default
+        #[arg(short = 'b', long = "browser", value_enum)]
+        browsers : Vec<BrowserArg>,
+
+        /// Print the manifests instead of writing them
+        #[arg(long = "print")]
+        print : bool,
+    },
+}
+
+/// The browsers the native-messaging host can be installed for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum BrowserArg {
+    /// Google Chrome
+    Chrome,
+    /// Chromium
+    Chromium,
+    /// Mozilla Firefox
+    Firefox,
+}
+
+impl From<BrowserArg> for Browser {
+    fn from(browser : BrowserArg) -> Self {
+        match browser {
+            BrowserArg::Chrome => Browser::Chrome,
+            BrowserArg::Chromium => Browser::Chromium,
+            BrowserArg::Firefox => Browser::Firefox,
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum QueueCommand {
+    /// List the queued URIs, oldest first
+    List {
+        /// The format of the output
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Human)]
+        format : OutputFormat,
+    },
+
+    /// Handle queued URIs again, removing those handled successfully
+    Retry {
+        /// The id of the queued URI, or the beginning of it
+        #[arg(required_unless_present = "all")]
+        id : Option<String>,
+
+        /// Retry every queued URI
+        #[arg(long = "all", conflicts_with = "id")]
+        all : bool,
+    },
+
+    /// Remove queued URIs without handling them
+    Purge {
+        /// The id of the queued URI, or the beginning of it
+        #[arg(required_unless_present = "all")]
+        id : Option<String>,
+
+        /// Remove every queued URI
+        #[arg(long = "all", conflicts_with = "id")]
+        all : bool,
+    },
+}
+
+/// The format used when writing results to the terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    /// Human readable text
+    Human,
+    /// JSON
+    Json,
+}
@@ -0,0 +1,651 @@
+//! Implementation of the protoHandler subcommands.
+//!
+//! Each function carries out one subcommand of the `Cli` and returns the exit
+//! code of the program.
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::ExitCode;
+use std::thread;
+#[cfg(unix)]
+use std::time::Duration;
+
+use clap::CommandFactory;
+use clap_complete::env::Shells;
+use clap_complete::Shell;
+use resolve_path::PathResolveExt;
+use serde::{Deserialize, Serialize};
+use simplelog::{error, info};
+#[cfg(unix)]
+use simplelog::warn;
+
+use protohandlers::audit;
+use protohandlers::config::{BatchMode, Config, HandlerKind, ProtocolConfig, ProtocolScriptConfig, ProtocolShellConfig};
+#[cfg(unix)]
+use protohandlers::daemon;
+use protohandlers::error::ProtoHandlerError;
+use protohandlers::explain::{check, explain, Explanation};
+use protohandlers::history::{self, HistoryEntry};
+use protohandlers::native::{self, Browser};
+use protohandlers::queue::{self, DeadLetter};
+use protohandlers::redact::redacted;
+use protohandlers::runner::{run_command, Invocation};
+
+use crate::cli::{BrowserArg, Cli, OutputFormat, RegisterArgs, COMPLETE_VAR};
+
+/// Prints the value in the given format, using `human` for human readable text
+fn print_as<T : Serialize>(value : &T, format : OutputFormat, human : impl FnOnce(&T)) {
+    match format {
+        OutputFormat::Human => human(value),
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(value).expect("Unable to serialize output")
+        ),
+    }
+}
+
+/// The outcome of processing one URI
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub(crate) struct UriResult {
+    /// The URI that was processed
+    pub uri : String,
+    /// True if the command ran and exited successfully
+    pub success : bool,
+    /// The exit code of the command, if it ran to completion
+    pub exit_code : Option<i32>,
+    /// The error that stopped the URI from being handled
+    pub error : Option<String>,
+}
+
+impl From<&Invocation> for UriResult {
+    fn from(invocation : &Invocation) -> Self {
+        Self {
+            uri : invocation.uri.clone(),
+            success : invocation.success(),
+            exit_code : invocation.exit_code(),
+            error : invocation.error(),
+        }
+    }
+}
+
+/// Runs the command for the URI and records it in the audit log and history
+fn handle(uri : String, config : &Config) -> UriResult {
+    let invocation = run_command(uri, config);
+    if let Err(e) = audit::record(&invocation, config) {
+        error!("{}", redacted(&e.to_string()));
+    }
+    if let Err(e) = history::record(&HistoryEntry::new(&invocation), config) {
+        error!("{}", redacted(&e.to_string()));
+    }
+    UriResult::from(&invocation)
+}
+
+impl fmt::Display for UriResult {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.success { "ok" } else { "failed" };
+        write!(f, "[{status}] {}", self.uri)?;
+        if let Some(code) = self.exit_code {
+            write!(f, " (exit code {code})")?;
+        }
+        if let Some(error) = &self.error {
+            write!(f, ": {error}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the command configured for each URI.
+///
+/// The URIs are processed one after the other, or all at once, depending on
+/// `batch.mode`.  When more than one URI is given a summary of the results is
+/// printed.  See `exit_code` for the exit code of the program.
+pub(crate) fn open(uris : Vec<String>, config : &Config, format : OutputFormat) -> ExitCode {
+    let results = match send_to_daemon(&uris, config) {
+        Some(results) => results,
+        None => process(uris, config),
+    };
+
+    for r in &results {
+        if r.success {
+            info!("{}", redacted(&r.to_string()));
+        } else {
+            error!("{}", redacted(&r.to_string()));
+        }
+    }
+    if results.len() > 1 || format == OutputFormat::Json {
+        print_as(&results, format, |results| {
+            for r in results {
+                println!("{r}");
+            }
+        });
+    }
+
+    exit_code(&results)
+}
+
+/// Sends the URIs to the daemon, if it is enabled and running.
+///
+/// Returns `None` if the URIs should be handled in this process instead.
+#[cfg(unix)]
+fn send_to_daemon(uris : &[String], config : &Config) -> Option<Vec<UriResult>> {
+    if !config.daemon.enabled {
+        return None;
+    }
+    let stream = match daemon::connect(&daemon::socket_path(config)) {
+        Ok(stream) => stream,
+        Err(e) => {
+            info!("{e}, handling the URIs in this process");
+            return None;
+        },
+    };
+    info!("Sending the URIs to the daemon");
+    let request = daemon::Request::Open { uris : uris.to_vec() };
+    match daemon::request(stream, &request, Duration::from_secs(config.daemon.timeout)) {
+        Ok(results) => Some(results),
+        Err(e @ ProtoHandlerError::DaemonTimeout { .. }) => {
+            warn!("{e}, handling the URIs in this process");
+            None
+        },
+        Err(e) => Some(
+            uris.iter()
+                .map(|uri| UriResult {
+                    uri : uri.clone(),
+                    success : false,
+                    exit_code : None,
+                    error : Some(e.to_string()),
+                })
+                .collect(),
+        ),
+    }
+}
+
+/// Sends the URIs to the daemon, which is only available on Unix
+#[cfg(not(unix))]
+fn send_to_daemon(_uris : &[String], _config : &Config) -> Option<Vec<UriResult>> {
+    None
+}
+
+/// Handles the URIs in this process, as configured in `batch.mode`
+pub(crate) fn process(uris : Vec<String>, config : &Config) -> Vec<UriResult> {
+    match config.batch.mode {
+        BatchMode::Sequential => uris
+            .into_iter()
+            .map(|uri| handle(uri, config))
+            .collect(),
+        BatchMode::Parallel => thread::scope(|scope| {
+            let handles : Vec<_> = uris
+                .into_iter()
+                .map(|uri| scope.spawn(move || handle(uri, config)))
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("URI processing thread panicked"))
+                .collect()
+        }),
+    }
+}
+
+/// Returns the exit code of the program for the results.
+///
+/// A single URI exits with the code of its handler, several URIs exit with
+/// failure if any of them failed.
+fn exit_code(results : &[UriResult]) -> ExitCode {
+    match results {
+        [r] if !r.success => r
+            .exit_code
+            .and_then(|code| u8::try_from(code).ok())
+            .filter(|code| *code != 0)
+            .map_or(ExitCode::FAILURE, ExitCode::from),
+        _ if results.iter().all(|r| r.success) => ExitCode::SUCCESS,
+        _ => ExitCode::FAILURE,
+    }
+}
+
+/// Prints how each URI would be handled, without running anything
+pub(crate) fn explain_uris(uris : &[String], config : &Config, format : OutputFormat) -> ExitCode {
+    let explanations : Vec<Explanation> = uris.iter().map(|uri| explain(uri, config)).collect();
+    if let [explanation] = explanations.as_slice() {
+        print_as(explanation, format, |e| print!("{e}"));
+    } else {
+        print_as(&explanations, format, |explanations| {
+            let text : Vec<String> = explanations.iter().map(ToString::to_string).collect();
+            print!("{}", text.join("\n"));
+        });
+    }
+    if explanations.iter().all(Explanation::is_ok) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Adds a protocol to the configuration file
+pub(crate) fn register(args : RegisterArgs, mut config : Config, path : &Path) -> ExitCode {
+    if let Some(existing) = config.protocols.iter().position(|p| p.name == args.name) {
+        if !args.force {
+            eprintln!("Protocol '{}' is already registered, use --force to replace it", args.name);
+            return ExitCode::FAILURE;
+        }
+        config.protocols.remove(existing);
+    }
+    config.protocols.push(ProtocolConfig {
+        name : args.name.clone(),
+        desc : args.desc,
+        script : ProtocolScriptConfig {
+            name : args.script_path,
+            args : args.script_args,
+        },
+        shell : ProtocolShellConfig {
+            name : args.shell,
+            args : Vec::new(),
+        },
+        ..ProtocolConfig::default()
+    });
+    save(&config, path, &format!("Registered protocol '{}'", args.name))
+}
+
+/// Removes a protocol from the configuration file
+pub(crate) fn unregister(name : &str, mut config : Config, path : &Path) -> ExitCode {
+    let count = config.protocols.len();
+    config.protocols.retain(|p| p.name != name);
+    if config.protocols.len() == count {
+        eprintln!("Protocol '{name}' is not registered");
+        return ExitCode::FAILURE;
+    }
+    save(&config, path, &format!("Unregistered protocol '{name}'"))
+}
+
+/// Saves the configuration, printing `message` on success
+fn save(config : &Config, path : &Path, message : &str) -> ExitCode {
+    match config.save(path) {
+        Ok(()) => {
+            println!("{message} in {}", path.display());
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Prints the configured protocols
+pub(crate) fn list(config : &Config, format : OutputFormat) -> ExitCode {
+    print_as(&config.protocols, format, |protocols| {
+        for p in protocols {
+            match p.kind {
+                HandlerKind::Script => println!("{}\t{}\t{} {}", p.name, p.desc, p.shell.name, p.script.name),
+                kind => println!("{}\t{}\t{kind}", p.name, p.desc),
+            }
+        }
+    });
+    ExitCode::SUCCESS
+}
+
+/// Prints the outcome of checking the configuration
+pub(crate) fn check_config(config : &Config, format : OutputFormat) -> ExitCode {
+    let checks = check(config);
+    print_as(&checks, format, |checks| {
+        for c in checks {
+            println!("{c}");
+        }
+    });
+    if checks.iter().all(|c| c.passed) {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// Prints the configuration as YAML
+pub(crate) fn show_config(config : &Config) -> ExitCode {
+    match serde_yml::to_string(config) {
+        Ok(yaml) => {
+            print!("{yaml}");
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            eprintln!("Could not serialize config {e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Prints the last `lines` lines of the log file
+pub(crate) fn show_log(config : &Config, lines : usize) -> ExitCode {
+    let path = config.logging.path.resolve();
+    match std::fs::read_to_string(&path) {
+        Ok(content) => {
+            let all : Vec<&str> = content.lines().collect();
+            for line in &all[all.len().saturating_sub(lines)..] {
+                println!("{line}");
+            }
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            eprintln!("Could not read log file {}: {e}", path.display());
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Prints the completion script for the given shell, the same as
+/// `COMPLETE=<shell> protohandlers`
+pub(crate) fn completions(shell : Shell) -> ExitCode {
+    let shells = Shells::builtins();
+    let Some(completer) = shells.completer(&shell.to_string()) else {
+        eprintln!("Completion is not supported for {shell}");
+        return ExitCode::FAILURE;
+    };
+    let name = Cli::command().get_name().to_string();
+    let program = std::env::current_exe().map_or_else(|_| name.clone(), |path| path.display().to_string());
+    match completer.write_registration(COMPLETE_VAR, &name, &name, &program, &mut io::stdout()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Could not write the completion script: {e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Prints the manual page, or writes a page for each subcommand to `out_dir`
+pub(crate) fn man(out_dir : Option<&Path>) -> ExitCode {
+    let cmd = Cli::command();
+    let result = match out_dir {
+        Some(dir) => std::fs::create_dir_all(dir).and_then(|()| clap_mangen::generate_to(cmd, dir)),
+        None => clap_mangen::Man::new(cmd).render(&mut io::stdout()),
+    };
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Could not generate manual page {e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Prints the recent invocations, newest first
+pub(crate) fn history_list(
+    config : &Config,
+    protocol : Option<&str>,
+    failed : bool,
+    limit : usize,
+    format : OutputFormat,
+) -> ExitCode {
+    let entries = match history::load(config) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        },
+    };
+    let entries : Vec<HistoryEntry> = entries
+        .iter()
+        .rev()
+        .filter(|e| protocol.is_none() || e.protocol.as_deref() == protocol)
+        .filter(|e| !failed || !e.success)
+        .take(limit)
+        .map(HistoryEntry::redacted)
+        .collect();
+    print_as(&entries, format, |entries| {
+        for e in entries {
+            println!("{e}");
+        }
+    });
+    ExitCode::SUCCESS
+}
+
+/// Prints the details of an invocation
+pub(crate) fn history_show(config : &Config, id : &str, format : OutputFormat) -> ExitCode {
+    let entries = match history::load(config) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        },
+    };
+    match history::find(&entries, id) {
+        Ok(entry) => {
+            print_as(&entry.redacted(), format, |e| {
+                println!("Id:        {}", e.id);
+                println!("Time:      {}", e.timestamp);
+                println!("URI:       {}", e.uri);
+                println!("Protocol:  {}", e.protocol.as_deref().unwrap_or("-"));
+                println!("Success:   {}", e.success);
+                if let Some(code) = e.exit_code {
+                    println!("Exit code: {code}");
+                }
+                if let Some(error) = &e.error {
+                    println!("Error:     {error}");
+                }
+                println!("Duration:  {} ms", e.duration_ms);
+                if let Some(replay_of) = &e.replay_of {
+                    println!("Replay of: {replay_of}");
+                }
+            });
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Runs a past invocation again.
+///
+/// The invocation is looked up in the history of `config`, and run with the
+/// protocols of `run_config`.  The replay is added to the history of `config`.
+pub(crate) fn history_replay(config : &Config, id : &str, run_config : &Config) -> ExitCode {
+    let entry = match history::load(config).and_then(|entries| history::find(&entries, id).cloned()) {
+        Ok(entry) => entry,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        },
+    };
+    if entry.uri_redacted {
+        eprintln!("{}", ProtoHandlerError::HistoryEntryRedacted { id : entry.id });
+        return ExitCode::FAILURE;
+    }
+    info!("Replaying invocation {}", entry.id);
+
+    let invocation = run_command(entry.uri.clone(), run_config);
+    if let Err(e) = audit::record(&invocation, run_config) {
+        error!("{}", redacted(&e.to_string()));
+    }
+    let mut replay = HistoryEntry::new(&invocation);
+    replay.replay_of = Some(entry.id);
+    if let Err(e) = history::record(&replay, config) {
+        error!("{}", redacted(&e.to_string()));
+    }
+
+    let result = UriResult::from(&invocation);
+    println!("{result}");
+    exit_code(&[result])
+}
+
+/// Loads the queued URIs with the given id, or every queued URI
+fn queued(config : &Config, id : Option<&str>) -> Result<Vec<DeadLetter>, ProtoHandlerError> {
+    let letters = queue::load(config)?;
+    match id {
+        Some(id) => queue::find(&letters, id).map(|letter| vec![letter.clone()]),
+        None => Ok(letters),
+    }
+}
+
+/// Prints the URIs of the dead-letter queue
+pub(crate) fn queue_list(config : &Config, format : OutputFormat) -> ExitCode {
+    match queue::load(config) {
+        Ok(letters) => {
+            print_as(&letters, format, |letters| {
+                for l in letters {
+                    println!("{l}");
+                }
+            });
+            ExitCode::SUCCESS
+        },
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Handles queued URIs again.
+///
+/// Each URI is removed from the queue before it is handled, a URI that fails
+/// again is queued again by its protocol.
+pub(crate) fn queue_retry(config : &Config, id : Option<&str>) -> ExitCode {
+    let letters = match queued(config, id) {
+        Ok(letters) => letters,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        },
+    };
+    let mut results = Vec::new();
+    for letter in letters {
+        if let Err(e) = queue::remove(&letter, config) {
+            eprintln!("{e}");
+            continue;
+        }
+        info!("Retrying queued invocation {}", letter.id);
+        let result = handle(letter.uri, config);
+        println!("{result}");
+        results.push(result);
+    }
+    exit_code(&results)
+}
+
+/// Removes queued URIs without handling them
+pub(crate) fn queue_purge(config : &Config, id : Option<&str>) -> ExitCode {
+    let letters = match queued(config, id) {
+        Ok(letters) => letters,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        },
+    };
+    let mut code = ExitCode::SUCCESS;
+    for letter in &letters {
+        match queue::remove(letter, config) {
+            Ok(()) => println!("Removed {}", letter.uri),
+            Err(e) => {
+                eprintln!("{e}");
+                code = ExitCode::FAILURE;
+            },
+        }
+    }
+    code
+}
+
+/// Handles the messages of the browser extension until the browser closes the
+/// connection
+pub(crate) fn native_host(config : &Config, args : &[String]) -> ExitCode {
+    info!("Native-messaging host started by {}", args.join(" "));
+    match native::serve(&mut io::stdin().lock(), &mut io::stdout().lock(), |uri| handle(uri, config)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Installs, or prints, the native-messaging host manifest of the browsers
+pub(crate) fn native_manifest(
+    config : &Config,
+    config_path : &Path,
+    extensions : &[String],
+    browsers : &[BrowserArg],
+    print : bool,
+) -> ExitCode {
+    let browsers : Vec<Browser> = if browsers.is_empty() {
+        vec![Browser::Chrome, Browser::Chromium, Browser::Firefox]
+    } else {
+        browsers.iter().map(|b| Browser::from(*b)).collect()
+    };
+    let dir = config.get_directory();
+    let launcher = dir.join("native-host");
+    if !print {
+        let program = match std::env::current_exe() {
+            Ok(program) => program,
+            Err(e) => {
+                eprintln!("Could not find the path of protohandlers: {e}");
+                return ExitCode::FAILURE;
+            },
+        };
+        #[cfg(unix)]
+        if let Err(e) = native::install_launcher(&dir, &program, config_path) {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = program;
+            eprintln!("Installing the native-messaging host is only supported on Unix, use --print");
+            return ExitCode::FAILURE;
+        }
+        println!("Wrote {}", launcher.display());
+    }
+
+    let mut code = ExitCode::SUCCESS;
+    for browser in browsers {
+        if print {
+            let manifest = browser.manifest(&launcher, extensions);
+            println!("# {}", browser.manifest_dir().join(format!("{}.json", native::HOST_NAME)).display());
+            println!("{}", serde_json::to_string_pretty(&manifest).expect("Unable to serialize output"));
+            continue;
+        }
+        match browser.install(&launcher, extensions) {
+            Ok(path) => println!("Wrote {}", path.display()),
+            Err(e) => {
+                eprintln!("{e}");
+                code = ExitCode::FAILURE;
+            },
+        }
+    }
+    code
+}
+
+/// Runs the daemon until it is stopped
+#[cfg(unix)]
+pub(crate) fn daemon_run(config : &Config) -> ExitCode {
+    match daemon::serve(&daemon::socket_path(config), |uris| process(uris, config)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{e}");
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Prints whether the daemon is running, or stops it
+#[cfg(unix)]
+pub(crate) fn daemon_control(config : &Config, request : &daemon::Request) -> ExitCode {
+    let path = daemon::socket_path(config);
+    let status : Result<daemon::Status, _> = daemon::connect(&path).and_then(|s| daemon::request(s, request, Duration::from_secs(config.daemon.timeout)));
+    match (status, request) {
+        (Ok(status), daemon::Request::Stop) => {
+            println!("Stopping daemon {} on {}", status.pid, status.socket);
+            ExitCode::SUCCESS
+        },
+        (Ok(status), _) => {
+            println!("Daemon {} is running on {}", status.pid, status.socket);
+            ExitCode::SUCCESS
+        },
+        (Err(_), _) => {
+            println!("Daemon is not running on {}", path.display());
+            ExitCode::FAILURE
+        },
+    }
+}
+
+/// Reports that the daemon is only available on Unix
+#[cfg(not(unix))]
+pub(crate) fn daemon_unsupported() -> ExitCode {
+    eprintln!("The daemon is only available on Unix");
+    ExitCode::FAILURE
+}
@@ -0,0 +1,188 @@
+//! Limits on the URIs of a protocol handled at the same time.
+//!
+//! The limits are enforced with lock files in the `locks` directory of the
+//! runtime directory, so that they apply across protoHandler processes.  The
+//! locks are released when the process handling the URI exits, even if it
+//! does not exit cleanly.
+
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{self, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use simplelog::info;
+
+use crate::config::{Config, ConcurrencyMode, ProtocolConfig};
+use crate::error::ProtoHandlerError;
+
+const LOCKS_DIR: &str = "locks";
+
+/// Allows a URI to be handled, until it is dropped
+#[derive(Debug)]
+pub struct Permit {
+    lock : Option<File>,
+    /// The path of the lock file, if it holds the time the URI was received
+    stamp : Option<PathBuf>,
+}
+
+impl Permit {
+    /// Forgets the time the URI was received, so that an identical URI is not
+    /// dropped as a duplicate.  This is used when the URI could not be
+    /// handled.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the lock file cannot be written.
+    pub fn forget(&mut self) -> Result<(), ProtoHandlerError> {
+        match (&self.lock, self.stamp.take()) {
+            (Some(file), Some(path)) => file.set_len(0).map_err(|e| ProtoHandlerError::LockError {
+                path : path.display().to_string(),
+                reason : e.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Whether a URI can be handled now
+#[derive(Debug)]
+pub enum Admission {
+    /// The URI can be handled while the permit is held
+    Admitted(Permit),
+    /// The URI must not be handled, for the given reason
+    Rejected(String),
+}
+
+/// Returns the directory of the lock files
+#[must_use] pub fn locks_dir(config : &Config) -> PathBuf {
+    config.get_runtime_directory().join(LOCKS_DIR)
+}
+
+/// Decides whether the URI can be handled now, as configured in the
+/// `concurrency` section of the protocol.
+///
+/// With the `serialize` mode, this waits until the other URIs of the protocol
+/// are handled.
+///
+/// # Errors
+///
+/// This function will return an error if a lock file cannot be created or
+/// locked.
+pub fn admit(protocol : &ProtocolConfig, uri : &str, config : &Config) -> Result<Admission, ProtoHandlerError> {
+    let concurrency = &protocol.concurrency;
+    let name = file_name(&protocol.name);
+    let dir = locks_dir(config);
+    let err = |path : &Path, e : io::Error| ProtoHandlerError::LockError {
+        path : path.display().to_string(),
+        reason : e.to_string(),
+    };
+    if concurrency.mode != ConcurrencyMode::Unlimited {
+        std::fs::create_dir_all(&dir).map_err(|e| err(&dir, e))?;
+    }
+
+    match concurrency.mode {
+        ConcurrencyMode::Unlimited => Ok(Admission::Admitted(Permit { lock : None, stamp : None })),
+        ConcurrencyMode::Serialize => {
+            let path = dir.join(format!("{name}.lock"));
+            let file = open(&path).map_err(|e| err(&path, e))?;
+            match file.try_lock() {
+                Ok(()) => {},
+                Err(TryLockError::WouldBlock) => {
+                    info!("Another URI of '{}' is being handled, waiting", protocol.name);
+                    file.lock().map_err(|e| err(&path, e))?;
+                },
+                Err(TryLockError::Error(e)) => return Err(err(&path, e)),
+            }
+            Ok(Admission::Admitted(Permit { lock : Some(file), stamp : None }))
+        },
+        ConcurrencyMode::Max => {
+            for slot in 0..concurrency.max {
+                let path = dir.join(format!("{name}.{slot}.lock"));
+                let file = open(&path).map_err(|e| err(&path, e))?;
+                match file.try_lock() {
+                    Ok(()) => return Ok(Admission::Admitted(Permit { lock : Some(file), stamp : None })),
+                    Err(TryLockError::WouldBlock) => {},
+                    Err(TryLockError::Error(e)) => return Err(err(&path, e)),
+                }
+            }
+            Ok(Admission::Rejected(format!(
+                "{} URIs of '{}' are already being handled",
+                concurrency.max, protocol.name
+            )))
+        },
+        ConcurrencyMode::DropDuplicates => {
+            prune(&dir, &name, concurrency.window);
+            let path = dir.join(format!("{name}.{:016x}.lock", fnv1a(uri)));
+            let mut file = open(&path).map_err(|e| err(&path, e))?;
+            match file.try_lock() {
+                Ok(()) => {},
+                Err(TryLockError::WouldBlock) => {
+                    return Ok(Admission::Rejected(String::from("an identical URI is being handled")));
+                },
+                Err(TryLockError::Error(e)) => return Err(err(&path, e)),
+            }
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+            let mut content = String::new();
+            file.read_to_string(&mut content).map_err(|e| err(&path, e))?;
+            if let Ok(received) = content.trim().parse::<u64>() {
+                let elapsed = now.saturating_sub(received);
+                if elapsed < concurrency.window {
+                    return Ok(Admission::Rejected(format!("an identical URI was received {elapsed} seconds ago")));
+                }
+            }
+            file.set_len(0)
+                .and_then(|()| file.rewind())
+                .and_then(|()| file.write_all(now.to_string().as_bytes()))
+                .map_err(|e| err(&path, e))?;
+            Ok(Admission::Admitted(Permit { lock : Some(file), stamp : Some(path) }))
+        },
+    }
+}
+
+/// Removes the `drop-duplicates` lock files of the protocol that no URI is
+/// being handled with, and whose time is older than `window` seconds or was
+/// forgotten
+fn prune(dir : &Path, name : &str, window : u64) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let prefix = format!("{name}.");
+    for entry in entries.flatten() {
+        let file_name = entry.file_name();
+        let Some(hash) = file_name.to_str().and_then(|f| f.strip_prefix(&prefix)).and_then(|f| f.strip_suffix(".lock")) else {
+            continue;
+        };
+        if hash.len() != 16 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            continue;
+        }
+        let stale = entry.metadata().is_ok_and(|m| {
+            m.len() == 0 || m.modified().ok().and_then(|t| t.elapsed().ok()).is_some_and(|e| e.as_secs() >= window)
+        });
+        if !stale {
+            continue;
+        }
+        // Held while removing, so that a URI being handled keeps its file
+        if let Ok(file) = open(&entry.path()) {
+            if file.try_lock().is_ok() {
+                let _ = std::fs::remove_file(entry.path());
+            }
+        }
+    }
+}
+
+/// Opens a lock file, creating it if needed
+fn open(path : &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).truncate(false).read(true).write(true).open(path)
+}
+
+/// Returns the protocol name, usable as a file name
+fn file_name(name : &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
+/// Hashes the URI with FNV-1a, which is stable across processes and builds
+pub(crate) fn fnv1a(text : &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, b| (hash ^ u64::from(b)).wrapping_mul(0x0100_0000_01b3))
+}
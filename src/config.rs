@@ -3,17 +3,24 @@
 //! It includes structures and functions for handling logging, shell configurations,
 //! and protocol configurations. The configuration is serialized and deserialized
 //! using YAML format.
+//!
+//! The fields documented as templates are rendered with the components of the
+//! URI, see the `template` module for their placeholders and filters.
 
-use std::{path::PathBuf, str::FromStr};
+use std::{collections::BTreeMap, fmt, path::{Path, PathBuf}, str::FromStr};
 
 use etcetera::BaseStrategy;
+use resolve_path::PathResolveExt;
 use log::error;
+use schemars::{schema_for, JsonSchema};
 use serde::{Deserialize, Serialize};
 use serde_yml;
 use simplelog::info;
 use simplelog::LevelFilter;
 
 use crate::error::ProtoHandlerError;
+use crate::routing::{self, Capture, CompiledRoute, RouteMatch};
+use crate::uri::ParsedUri;
 
 // APP_NAME and CONFIG_EXT are used to determine the directory and file name of
 // the serialized Config
@@ -22,7 +29,7 @@ const CONFIG_EXT: &str = ".yml";
 
 // --------------------------------------------------------------------------------
 // region: Config
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
 /// Represents the configuration for the `protohandler` application.
 pub struct Config {
     /// Logging configuration.
@@ -31,6 +38,35 @@ pub struct Config {
     pub shells: Vec<ShellConfig>,
     /// List of protocol configurations.
     pub protocols: Vec<ProtocolConfig>,
+    /// How several URIs given in one invocation are processed.
+    #[serde(default)]
+    pub batch: BatchConfig,
+    /// Audit log configuration.
+    #[serde(default)]
+    pub audit: AuditConfig,
+    /// Redaction of sensitive information in logs.
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    /// Invocation history configuration.
+    #[serde(default)]
+    pub history: HistoryConfig,
+    /// What to do with URIs whose scheme is not configured.
+    #[serde(default)]
+    pub fallback: FallbackConfig,
+    /// Desktop notification configuration.
+    #[serde(default)]
+    pub notifications: NotificationsConfig,
+    /// Resident daemon configuration.
+    #[serde(default)]
+    pub daemon: DaemonConfig,
+    /// Directory of the runtime files, such as the daemon socket and the lock
+    /// files, defaults to `$XDG_RUNTIME_DIR/protohandler`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub runtime_dir: Option<String>,
+    /// Directory of the persistent state, such as the history and the URIs
+    /// recently received, defaults to `$XDG_STATE_HOME/protohandler`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state_dir: Option<String>,
 }
 
 impl Default for Config {
@@ -54,6 +90,15 @@ impl Default for Config {
             logging: LoggingConfig::default(),
             shells: vec![pwsh, python],
             protocols: Vec::new(),
+            batch: BatchConfig::default(),
+            audit: AuditConfig::default(),
+            redaction: RedactionConfig::default(),
+            history: HistoryConfig::default(),
+            fallback: FallbackConfig::default(),
+            notifications: NotificationsConfig::default(),
+            daemon: DaemonConfig::default(),
+            runtime_dir: None,
+            state_dir: None,
         }
     }
 }
@@ -64,12 +109,51 @@ impl Config {
     }
 
     /// Returns the directory where the `protohandler` configuration is stored.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the user's home directory cannot be determined.
     #[must_use] pub fn get_directory(&self) -> PathBuf {
         let strategy = etcetera::choose_base_strategy().expect("Unable to find config directory");
 
         strategy.config_dir().join(APP_NAME)
     }
 
+    /// Returns the directory where the `protohandler` state is stored.
+    ///
+    /// This is `state_dir` if it is set, the XDG state directory where there
+    /// is one, and the data directory otherwise.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the user's home directory cannot be determined.
+    #[must_use] pub fn get_state_directory(&self) -> PathBuf {
+        if let Some(dir) = &self.state_dir {
+            return dir.resolve().into_owned();
+        }
+        let strategy = etcetera::choose_base_strategy().expect("Unable to find state directory");
+
+        strategy.state_dir().unwrap_or_else(|| strategy.data_dir()).join(APP_NAME)
+    }
+
+    /// Returns the directory where the `protohandler` runtime files, such as
+    /// the daemon socket, are stored.
+    ///
+    /// This is `runtime_dir` if it is set, `$XDG_RUNTIME_DIR` where there is
+    /// one, and the state directory otherwise.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the user's home directory cannot be determined.
+    #[must_use] pub fn get_runtime_directory(&self) -> PathBuf {
+        if let Some(dir) = &self.runtime_dir {
+            return dir.resolve().into_owned();
+        }
+        let strategy = etcetera::choose_base_strategy().expect("Unable to find runtime directory");
+
+        strategy.runtime_dir().map_or_else(|| self.get_state_directory(), |dir| dir.join(APP_NAME))
+    }
+
     /// Returns the file path of the `protohandler` configuration file.
     #[must_use] pub fn get_file(&self) -> PathBuf {
         let dir = self.get_directory();
@@ -88,24 +172,94 @@ impl Config {
     ///
     /// * `Ok(())` - If the configuration is successfully loaded.
     /// * `Err(ProtoHandlerError)` - If there is an error in loading the configuration.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the path is invalid, the file
+    /// cannot be read or its content is not a valid configuration.
     pub fn load(&mut self, path: String) -> Result<(), ProtoHandlerError> {
         match PathBuf::from_str(&path) {
-            Ok(file) => {
-                if let Ok(content) = std::fs::read_to_string(file) {
+            Ok(file) => match std::fs::read_to_string(file) {
+                Ok(content) => {
                     info!("Loading configuration from {path}");
-                    let config: Config = serde_yml::from_str(&content).unwrap();
+                    let config: Config = serde_yml::from_str(&content).map_err(|e| {
+                        ProtoHandlerError::ConfigParseError { path: path.clone(), reason: e.to_string() }
+                    })?;
                     self.logging = config.logging;
                     self.shells = config.shells;
                     self.protocols = config.protocols;
+                    self.batch = config.batch;
+                    self.audit = config.audit;
+                    self.redaction = config.redaction;
+                    self.history = config.history;
+                    self.fallback = config.fallback;
+                    self.notifications = config.notifications;
+                    self.daemon = config.daemon;
+                    self.runtime_dir = config.runtime_dir;
+                    self.state_dir = config.state_dir;
+                    self.compile_routes();
                     Ok(())
-                } else {
+                }
+                Err(e) => {
                     error!("Could not load config file {path}");
-                    Err(ProtoHandlerError::ConfigParseError { path })
+                    Err(ProtoHandlerError::ConfigParseError { path, reason: e.to_string() })
                 }
-            }
+            },
             Err(_e) => Err(ProtoHandlerError::PathError { path }),
         }
     }
+
+    /// Compiles the path patterns and regular expressions of the routes, so
+    /// that they are not compiled for every URI.  The routes that are not
+    /// compiled are compiled when they are matched.
+    pub fn compile_routes(&mut self) {
+        for route in self.protocols.iter_mut().flat_map(|p| p.routes.iter_mut()) {
+            route.compiled = Some(CompiledRoute::new(route));
+        }
+    }
+
+    /// Saves the configuration to the specified path.
+    ///
+    /// The directory of the file is created if it does not exist.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the configuration file.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the configuration cannot be
+    /// serialized or the file cannot be written.
+    pub fn save(&self, path: &Path) -> Result<(), ProtoHandlerError> {
+        let err = || ProtoHandlerError::ConfigWriteError {
+            path: path.display().to_string(),
+        };
+        let content = serde_yml::to_string(self).map_err(|_| err())?;
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(|_| err())?;
+        }
+        std::fs::write(path, content).map_err(|_| err())?;
+        info!("Saved configuration to {}", path.display());
+        Ok(())
+    }
+
+    /// Generates the JSON Schema of the configuration file.
+    ///
+    /// The schema is derived from the configuration types, using their doc
+    /// comments as descriptions.  It can be used by editors to validate and
+    /// complete the YAML configuration file.
+    ///
+    /// # Returns
+    ///
+    /// * `String` - The schema as pretty-printed JSON.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the schema cannot be serialized to JSON.
+    #[must_use] pub fn schema() -> String {
+        let schema = schema_for!(Config);
+        serde_json::to_string_pretty(&schema).expect("Unable to serialize the config schema")
+    }
 }
 
 // endregion Config
@@ -114,24 +268,83 @@ impl Config {
 // --------------------------------------------------------------------------------
 // region: Logging config
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 /// Represents the logging levels for the `protohandler` application.
 pub enum LoggingLevel {
+    /// Log everything, including debugging information.
     Debug,
+    /// Log informational messages, warnings and errors.
     Info,
+    /// Log warnings and errors.
     Warn,
+    /// Log errors only.
     Error,
+    /// Disable logging.
     None,
 }
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+#[serde(rename_all = "lowercase")]
+/// Represents when log messages are written to the terminal.
+pub enum TerminalLogging {
+    /// Only when protoHandler is run from a terminal.
+    #[default]
+    Auto,
+    /// Always, even when not run from a terminal.
+    Always,
+    /// Never.
+    Never,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
 /// Represents the logging configuration for the `protohandler` application.
 pub struct LoggingConfig {
     /// Path to the log file.
     pub path: String,
     /// Logging level.
     pub level: LoggingLevel,
+    /// Logging level of the terminal, defaults to `level`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub terminal_level: Option<LoggingLevel>,
+    /// Logging level of the log file, defaults to `level`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_level: Option<LoggingLevel>,
+    /// When log messages are written to the terminal.
+    #[serde(default)]
+    pub terminal: TerminalLogging,
+    /// Append to the log file instead of replacing it on every invocation.
+    #[serde(default = "default_append")]
+    pub append: bool,
+    /// Rotate the log file when it is larger than this many bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// Number of rotated log files to keep.
+    #[serde(default = "default_max_files")]
+    pub max_files: usize,
+    /// Format of the time in log messages: 'rfc3339', 'rfc2822' or a `time`
+    /// format description such as '[hour]:[minute]:[second]'.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_format: Option<String>,
+    /// Most verbose level at which the thread id is included in log messages.
+    #[serde(default = "default_detail_level")]
+    pub thread: LoggingLevel,
+    /// Most verbose level at which the module is included in log messages.
+    #[serde(default = "default_detail_level")]
+    pub target: LoggingLevel,
+}
+
+fn default_append() -> bool {
+    true
+}
+
+fn default_max_files() -> usize {
+    3
+}
+
+fn default_detail_level() -> LoggingLevel {
+    LoggingLevel::Debug
 }
 
 impl Default for LoggingConfig {
@@ -139,6 +352,27 @@ impl Default for LoggingConfig {
         Self {
             path: String::from("protocolhandler.log"),
             level: LoggingLevel::Info,
+            terminal_level: None,
+            file_level: None,
+            terminal: TerminalLogging::default(),
+            append: default_append(),
+            max_size: None,
+            max_files: default_max_files(),
+            time_format: None,
+            thread: default_detail_level(),
+            target: default_detail_level(),
+        }
+    }
+}
+
+impl From<&LoggingLevel> for LevelFilter {
+    fn from(level: &LoggingLevel) -> Self {
+        match level {
+            LoggingLevel::Debug => LevelFilter::Debug,
+            LoggingLevel::Info => LevelFilter::Info,
+            LoggingLevel::Warn => LevelFilter::Warn,
+            LoggingLevel::Error => LevelFilter::Error,
+            LoggingLevel::None => LevelFilter::Off,
         }
     }
 }
@@ -150,22 +384,347 @@ impl LoggingConfig {
     ///
     /// * `LevelFilter` - The corresponding `simplelog` logging level.
     #[must_use] pub fn convert_level(&self) -> LevelFilter {
-        match self.level {
-            LoggingLevel::Debug => LevelFilter::Debug,
-            LoggingLevel::Info => LevelFilter::Info,
-            LoggingLevel::Warn => LevelFilter::Warn,
-            LoggingLevel::Error => LevelFilter::Error,
-            LoggingLevel::None => LevelFilter::Off,
-        }
+        LevelFilter::from(&self.level)
+    }
+
+    /// Returns the `simplelog` logging level of the terminal.
+    #[must_use] pub fn terminal_level(&self) -> LevelFilter {
+        LevelFilter::from(self.terminal_level.as_ref().unwrap_or(&self.level))
+    }
+
+    /// Returns the `simplelog` logging level of the log file.
+    #[must_use] pub fn file_level(&self) -> LevelFilter {
+        LevelFilter::from(self.file_level.as_ref().unwrap_or(&self.level))
     }
 }
 // endregion Logging config
 // --------------------------------------------------------------------------------
 
+// --------------------------------------------------------------------------------
+// region: Batch config
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+#[serde(rename_all = "lowercase")]
+/// Represents how several URIs given in one invocation are processed.
+pub enum BatchMode {
+    /// Process the URIs one after the other, in the order they were given.
+    #[default]
+    Sequential,
+    /// Process all of the URIs at the same time.
+    Parallel,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+/// Represents the configuration for processing several URIs in one invocation.
+pub struct BatchConfig {
+    /// Whether the URIs are processed sequentially or in parallel.
+    #[serde(default)]
+    pub mode: BatchMode,
+}
+
+// endregion Batch config
+// --------------------------------------------------------------------------------
+
+// --------------------------------------------------------------------------------
+// region: Audit config
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+/// Represents the configuration of the audit log.
+///
+/// The audit log records every URI handled as one JSON object per line.  It
+/// is separate from the log file configured in `logging`.
+pub struct AuditConfig {
+    /// Write a record of every URI handled.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Path to the audit log, defaults to `audit.jsonl` in the state directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+}
+
+// endregion Audit config
+// --------------------------------------------------------------------------------
+
+// --------------------------------------------------------------------------------
+// region: History config
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+/// Represents the configuration of the invocation history.
+///
+/// The history keeps the recent URIs and their outcome, so that they can be
+/// inspected and replayed.
+pub struct HistoryConfig {
+    /// Keep a history of the URIs handled.
+    #[serde(default = "default_history_enabled")]
+    pub enabled: bool,
+    /// Path to the history, defaults to `history.jsonl` in the state directory.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    /// Number of entries to keep.
+    #[serde(default = "default_history_entries")]
+    pub max_entries: usize,
+    /// Store the URIs as they are instead of redacted, so that the URIs with
+    /// sensitive parts can be replayed.  Replaying an entry whose URI was
+    /// redacted is refused.
+    #[serde(default)]
+    pub raw_uris: bool,
+}
+
+fn default_history_enabled() -> bool {
+    true
+}
+
+fn default_history_entries() -> usize {
+    500
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_history_enabled(),
+            path: None,
+            max_entries: default_history_entries(),
+            raw_uris: false,
+        }
+    }
+}
+
+// endregion History config
+// --------------------------------------------------------------------------------
+
+// --------------------------------------------------------------------------------
+// region: Fallback config
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+#[serde(rename_all = "lowercase")]
+/// Represents what is done with a URI whose scheme is not configured.
+pub enum FallbackAction {
+    /// Log an error.
+    #[default]
+    Log,
+    /// Log an error and show a desktop notification, sent as configured in
+    /// `notifications`.
+    Notify,
+    /// Hand the URI off to `open_program`, e.g. `xdg-open`.
+    Open,
+    /// Handle the URI given in `uri` instead.
+    Forward,
+    /// Log a warning and exit with `exit_code`.
+    Exit,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+/// Represents the handling of URIs whose scheme is not configured.
+///
+/// A protocol named `*` handles every scheme that is not configured, so the
+/// fallback is only used when there is no such protocol.
+pub struct FallbackConfig {
+    /// What to do with the URI.
+    #[serde(default)]
+    pub action: FallbackAction,
+    /// Program the URI is handed off to.
+    #[serde(default = "default_open_program")]
+    pub open_program: String,
+    /// Template of the URI handled instead.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub uri: Option<String>,
+    /// Exit code of protoHandler, 0 to ignore the URI.
+    #[serde(default = "default_fallback_exit_code")]
+    pub exit_code: i32,
+}
+
+fn default_notify_program() -> String {
+    String::from("notify-send")
+}
+
+fn default_open_program() -> String {
+    String::from("xdg-open")
+}
+
+fn default_fallback_exit_code() -> i32 {
+    1
+}
+
+impl Default for FallbackConfig {
+    fn default() -> Self {
+        Self {
+            action: FallbackAction::default(),
+            open_program: default_open_program(),
+            uri: None,
+            exit_code: default_fallback_exit_code(),
+        }
+    }
+}
+
+// endregion Fallback config
+// --------------------------------------------------------------------------------
+
+// --------------------------------------------------------------------------------
+// region: Notifications config
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+#[serde(rename_all = "lowercase")]
+/// Represents how desktop notifications are sent.
+pub enum NotificationBackend {
+    /// Use the D-Bus interface where it is available, and `program` otherwise.
+    #[default]
+    Auto,
+    /// Use the freedesktop notification D-Bus interface.
+    Dbus,
+    /// Run `program` with `args`.
+    Command,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+/// Represents how the desktop notifications of the protocols are sent.
+///
+/// The `title`, `body` and `args` are templates, which can also use
+/// `{protocol}`, `{route}`, `{status}` (`succeeded` or `failed`), `{error}`,
+/// `{exit_code}` and `{urgency}` (`normal` or `critical`), and `args` can use
+/// `{title}` and `{body}`.
+pub struct NotificationsConfig {
+    /// How the notifications are sent.
+    #[serde(default)]
+    pub backend: NotificationBackend,
+    /// Program showing the notification, for the `command` backend.
+    #[serde(default = "default_notify_program")]
+    pub program: String,
+    /// Arguments of the program.
+    #[serde(default = "default_notify_args")]
+    pub args: Vec<String>,
+    /// Default title of the notifications.
+    #[serde(default = "default_notify_title")]
+    pub title: String,
+    /// Default body of the notifications.
+    #[serde(default = "default_notify_body")]
+    pub body: String,
+}
+
+fn default_notify_args() -> Vec<String> {
+    ["--app-name=protoHandler", "--urgency={urgency}", "{title}", "{body}"]
+        .map(String::from)
+        .to_vec()
+}
+
+fn default_notify_title() -> String {
+    String::from("{protocol} {status}")
+}
+
+fn default_notify_body() -> String {
+    String::from("{uri}\n{error}")
+}
+
+impl Default for NotificationsConfig {
+    fn default() -> Self {
+        Self {
+            backend: NotificationBackend::default(),
+            program: default_notify_program(),
+            args: default_notify_args(),
+            title: default_notify_title(),
+            body: default_notify_body(),
+        }
+    }
+}
+
+// endregion Notifications config
+// --------------------------------------------------------------------------------
+
+// --------------------------------------------------------------------------------
+// region: Daemon config
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+/// Represents the configuration of the resident daemon.
+///
+/// The daemon keeps the configuration loaded and handles the URIs sent to it
+/// on a Unix socket, so that each click does not pay for starting up.
+pub struct DaemonConfig {
+    /// Send the URIs to the daemon when it is running.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Path to the socket, defaults to `protohandler.sock` in the runtime
+    /// directory (`$XDG_RUNTIME_DIR/protohandler`).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub socket: Option<String>,
+    /// Seconds to wait for the daemon to reply before handling the URIs in
+    /// this process, 0 to wait until it replies.  A URI the daemon is still
+    /// handling is then handled twice, so this should exceed the time taken
+    /// by the slowest handler.
+    #[serde(default = "default_daemon_timeout")]
+    pub timeout: u64,
+}
+
+fn default_daemon_timeout() -> u64 {
+    60
+}
+
+impl Default for DaemonConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            socket: None,
+            timeout: default_daemon_timeout(),
+        }
+    }
+}
+
+// endregion Daemon config
+// --------------------------------------------------------------------------------
+
+// --------------------------------------------------------------------------------
+// region: Redaction config
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+/// Represents the redaction of sensitive information in logs.
+///
+/// Redaction applies wherever a URI or a commandline is logged, including the
+/// audit log.
+pub struct RedactionConfig {
+    /// Redact sensitive information in logs.
+    #[serde(default = "default_redaction_enabled")]
+    pub enabled: bool,
+    /// Names of the query parameters whose values are redacted, ignoring case.
+    #[serde(default = "default_redacted_keys")]
+    pub query_keys: Vec<String>,
+    /// Regular expressions matched against the whole URI, every match is
+    /// redacted.
+    #[serde(default)]
+    pub patterns: Vec<String>,
+    /// Redact the user information (`user:password@`) of URIs.
+    #[serde(default = "default_redaction_enabled")]
+    pub userinfo: bool,
+}
+
+fn default_redaction_enabled() -> bool {
+    true
+}
+
+fn default_redacted_keys() -> Vec<String> {
+    ["token", "password", "secret", "sig"].map(String::from).to_vec()
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_redaction_enabled(),
+            query_keys: default_redacted_keys(),
+            patterns: Vec::new(),
+            userinfo: default_redaction_enabled(),
+        }
+    }
+}
+
+// endregion Redaction config
+// --------------------------------------------------------------------------------
+
 // --------------------------------------------------------------------------------
 // region: Shells config
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
 #[derive(Default)]
 /// Represents the configuration for a shell in the `protohandler` application.
 pub struct ShellConfig {
@@ -183,7 +742,7 @@ pub struct ShellConfig {
 // --------------------------------------------------------------------------------
 // region: Protocols config
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
 #[derive(Default)]
 /// Represents the configuration for a protocol in the `protohandler` application
 ///
@@ -193,28 +752,625 @@ pub struct ProtocolConfig {
     pub name : String,
     /// A short description of the protocol
     pub desc : String,
-    /// The script to call
+    /// Rules rewriting the URIs of the protocol before they are handled
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub rewrite : Vec<RewriteRule>,
+    /// Routes handling some subcommands differently
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routes : Vec<RouteConfig>,
+    /// How many URIs of the protocol can be handled at the same time
+    #[serde(default)]
+    pub concurrency : ConcurrencyConfig,
+    /// Seconds during which a URI identical to one already received is
+    /// skipped, 0 to handle every URI
+    #[serde(default)]
+    pub dedupe_window : u64,
+    /// How the URIs the handler failed to handle are retried
+    #[serde(default)]
+    pub retry : RetryConfig,
+    /// When a desktop notification is shown for the URIs of the protocol
+    #[serde(default)]
+    pub notify : NotifyConfig,
+    /// How the URIs of the protocol are split into their components
+    #[serde(default)]
+    pub parser : UriParser,
+    /// The kind of handler for the protocol
+    #[serde(default)]
+    pub kind : HandlerKind,
+    /// The script to call, for the `script` kind
+    #[serde(default)]
     pub script : ProtocolScriptConfig,
-    /// The shell to use when calling the script
+    /// The shell to use when calling the script, for the `script` kind
+    #[serde(default)]
     pub shell : ProtocolShellConfig,
+    /// The program to run, for the `exec` kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec : Option<ExecConfig>,
+    /// The file to append to, for the `append-file` kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub append_file : Option<AppendFileConfig>,
+    /// The endpoint to send the payload to, for the `http` kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http : Option<HttpConfig>,
+    /// The URI to dispatch instead, for the `forward` kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward : Option<ForwardConfig>,
+    /// The values captured by the path pattern of the route handling the URI,
+    /// set by `runner::lookup_route`
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub captures : Vec<Capture>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
+#[derive(Default)]
+#[serde(rename_all = "kebab-case")]
+/// Represents how the URIs of a protocol are split into their components
+pub enum UriParser {
+    /// `proto://subcommand/path?name=val&name=val#fragment`
+    #[default]
+    Standard,
+    /// Emacs `org-protocol` URIs, in the `proto://subcommand?name=val` form or
+    /// the old `proto://subcommand:/val/val/val` positional form
+    OrgProtocol,
+    /// `mailto:` URIs, with the `to`, `subject`, `body`, `cc` and `bcc` fields
+    Mailto,
+    /// `magnet:` links, with their `xt`, `dn` and `tr` fields and the `hash`,
+    /// `name` and `trackers` derived from them
+    Magnet,
+    /// `ssh://` URIs, with the `user`, `host`, `port` and `fingerprint` fields;
+    /// the host is the subcommand
+    Ssh,
+    /// `tel:` URIs, with the `number`, `digits` and `ext` fields
+    Tel,
+    /// `file:` URIs, with the `host` and decoded `path` fields; the host is the
+    /// subcommand
+    File,
+}
+
+impl UriParser {
+    /// Returns true if the parser handles URIs without an authority, such as
+    /// `mailto:me@example.com`
+    #[must_use] pub fn is_opaque(self) -> bool {
+        matches!(self, Self::Mailto | Self::Magnet | Self::Ssh | Self::Tel | Self::File)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
+#[derive(Default)]
+#[serde(rename_all = "kebab-case")]
+/// Represents how a protocol handles its URIs
+pub enum HandlerKind {
+    /// Run `script` with `shell`, passing the URI
+    #[default]
+    Script,
+    /// Run the program in `exec` directly
+    Exec,
+    /// Append a templated line to the file in `append_file`
+    AppendFile,
+    /// Send the payload to the endpoint in `http`
+    Http,
+    /// Rewrite the URI and dispatch it again, as configured in `forward`
+    Forward,
+}
+
+impl fmt::Display for HandlerKind {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Script => "script",
+            Self::Exec => "exec",
+            Self::AppendFile => "append-file",
+            Self::Http => "http",
+            Self::Forward => "forward",
+        };
+        f.write_str(name)
+    }
 }
 
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
 #[derive(Default)]
+/// Represents the script called for a protocol
 pub struct ProtocolScriptConfig {
+    /// Path to the script
     pub name : String,
+    /// Arguments placed after the script, before the URI
     pub args : Vec<String>,
 }
 
 
-#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
 #[derive(Default)]
+/// Represents the shell used to call the script of a protocol
 pub struct ProtocolShellConfig {
+    /// Name of a shell listed in `shells`
     pub name : String,
+    /// Additional arguments to the shell for this script
     pub args : Vec<String>
 }
 
 
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+/// Represents a program run directly for a protocol
+///
+/// The arguments are templates.
+pub struct ExecConfig {
+    /// Path or name of the program
+    pub program : String,
+    /// Arguments to the program
+    #[serde(default)]
+    pub args : Vec<String>,
+}
+
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+/// Represents a file that a templated entry is appended to for a protocol
+///
+/// The path and the entry are templates, such as
+/// `{date:%Y-%m-%d} {title|trim|default:Untitled}`.
+pub struct AppendFileConfig {
+    /// Template of the path to the file, e.g. `notes/{date:%Y-%m-%d}.md`
+    pub path : String,
+    /// Template of the entry appended to the file, a newline is added if it
+    /// does not end with one
+    pub template : String,
+}
+
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+/// Represents an HTTP endpoint that the payload is sent to for a protocol
+///
+/// The URL, the header values and the fields are templates.  A 2xx response is
+/// a success, a 4xx response exits with code 4, a 5xx response with code 5 and
+/// any other response with code 1.
+pub struct HttpConfig {
+    /// Template of the URL of the endpoint
+    pub url : String,
+    /// The HTTP method
+    #[serde(default)]
+    pub method : HttpMethod,
+    /// Headers sent with the request, e.g. `Authorization`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers : BTreeMap<String, String>,
+    /// How the body of the request is built.  When not set, the body is
+    /// `json`, except for `GET` and `DELETE` which send the fields in the query
+    /// string of the URL instead
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body : Option<HttpBody>,
+    /// The fields of the body.  When empty, the body holds the query
+    /// parameters of the URI, and the URI and its components for `json`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields : BTreeMap<String, String>,
+    /// Seconds to wait for the endpoint to answer
+    #[serde(default = "default_http_timeout")]
+    pub timeout : u64,
+    /// Number of times the request is sent again when the endpoint cannot be
+    /// reached or answers with a 5xx status
+    #[serde(default)]
+    pub retries : u32,
+    /// Milliseconds to wait before sending the request again
+    #[serde(default = "default_http_retry_delay")]
+    pub retry_delay : u64,
+}
+
+fn default_http_timeout() -> u64 {
+    10
+}
+
+fn default_http_retry_delay() -> u64 {
+    500
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            url : String::new(),
+            method : HttpMethod::default(),
+            headers : BTreeMap::new(),
+            body : None,
+            fields : BTreeMap::new(),
+            timeout : default_http_timeout(),
+            retries : 0,
+            retry_delay : default_http_retry_delay(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
+#[derive(Default)]
+#[serde(rename_all = "UPPERCASE")]
+/// Represents the method of an HTTP request
+pub enum HttpMethod {
+    /// GET, with no body
+    Get,
+    /// POST
+    #[default]
+    Post,
+    /// PUT
+    Put,
+    /// PATCH
+    Patch,
+    /// DELETE, with no body
+    Delete,
+}
+
+impl HttpMethod {
+    /// Returns true if requests of the method usually have a body
+    #[must_use] pub fn has_body(self) -> bool {
+        !matches!(self, Self::Get | Self::Delete)
+    }
+}
+
+impl fmt::Display for HttpMethod {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Get => "GET",
+            Self::Post => "POST",
+            Self::Put => "PUT",
+            Self::Patch => "PATCH",
+            Self::Delete => "DELETE",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
+#[derive(Default)]
+#[serde(rename_all = "lowercase")]
+/// Represents how the body of an HTTP request is built
+pub enum HttpBody {
+    /// A JSON object
+    #[default]
+    Json,
+    /// URL encoded form fields
+    Form,
+    /// No body
+    None,
+}
+
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+/// Represents the rewriting of a URI that is dispatched again
+pub struct ForwardConfig {
+    /// Template of the new URI
+    pub uri : String,
+}
+
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
+#[derive(Default)]
+#[serde(rename_all = "kebab-case")]
+/// Represents how the URIs of a protocol handled at the same time are limited
+pub enum ConcurrencyMode {
+    /// Handle every URI as it comes
+    #[default]
+    Unlimited,
+    /// Reject the URIs received while `max` URIs are being handled
+    Max,
+    /// Handle the URIs one at a time, the others wait for their turn
+    Serialize,
+    /// Reject a URI identical to one being handled, or received less than
+    /// `window` seconds ago
+    DropDuplicates,
+}
+
+impl fmt::Display for ConcurrencyMode {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Unlimited => "unlimited",
+            Self::Max => "max",
+            Self::Serialize => "serialize",
+            Self::DropDuplicates => "drop-duplicates",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+/// Represents the concurrency limits of a protocol
+///
+/// The limits are enforced with lock files in the runtime directory, so they
+/// apply across protoHandler processes.
+pub struct ConcurrencyConfig {
+    /// How the URIs handled at the same time are limited
+    #[serde(default)]
+    pub mode : ConcurrencyMode,
+    /// Number of URIs handled at the same time, for `max`
+    #[serde(default = "default_concurrency_max")]
+    pub max : usize,
+    /// Seconds during which an identical URI is rejected, for
+    /// `drop-duplicates`
+    #[serde(default)]
+    pub window : u64,
+    /// Exit code of the rejected URIs
+    #[serde(default = "default_rejected_exit_code")]
+    pub exit_code : i32,
+}
+
+fn default_concurrency_max() -> usize {
+    1
+}
+
+fn default_rejected_exit_code() -> i32 {
+    75
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            mode : ConcurrencyMode::default(),
+            max : default_concurrency_max(),
+            window : 0,
+            exit_code : default_rejected_exit_code(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize, PartialEq, Eq, JsonSchema)]
+#[derive(Default)]
+#[serde(rename_all = "lowercase")]
+/// Represents the outcomes of a URI a notification is shown for
+pub enum NotifyOn {
+    /// Never show a notification
+    #[default]
+    Never,
+    /// Show a notification when the URI could not be handled
+    Error,
+    /// Show a notification when the URI was handled
+    Success,
+    /// Show a notification for every URI
+    Always,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+/// Represents the desktop notifications of a protocol
+///
+/// The title and body default to those of the `notifications` section.
+pub struct NotifyConfig {
+    /// The outcomes a notification is shown for
+    #[serde(default)]
+    pub on : NotifyOn,
+    /// Template of the title
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title : Option<String>,
+    /// Template of the body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub body : Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+/// Represents how a protocol retries the URIs its handler failed to handle
+///
+/// The delay before each retry is `backoff` milliseconds, multiplied by
+/// `backoff_factor` after every retry.  Only the commands that fail and IO
+/// errors are retried; the `http` kind retries with `HttpConfig::retries`.
+pub struct RetryConfig {
+    /// Number of times the handler is run before giving up, 1 to never retry
+    #[serde(default = "default_retry_attempts")]
+    pub max_attempts : u32,
+    /// Milliseconds to wait before the first retry
+    #[serde(default = "default_retry_backoff")]
+    pub backoff : u64,
+    /// Factor the delay is multiplied by after every retry
+    #[serde(default = "default_retry_backoff_factor")]
+    pub backoff_factor : u32,
+    /// Store the URI in the dead-letter queue when every attempt failed
+    #[serde(default)]
+    pub dead_letter : bool,
+}
+
+fn default_retry_attempts() -> u32 {
+    1
+}
+
+fn default_retry_backoff() -> u64 {
+    1000
+}
+
+fn default_retry_backoff_factor() -> u32 {
+    2
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts : default_retry_attempts(),
+            backoff : default_retry_backoff(),
+            backoff_factor : default_retry_backoff_factor(),
+            dead_letter : false,
+        }
+    }
+}
+
+impl RetryConfig {
+    /// Returns the delay before the given retry, counting from 1
+    #[must_use] pub fn delay(&self, retry : u32) -> std::time::Duration {
+        let factor = u64::from(self.backoff_factor).saturating_pow(retry.saturating_sub(1));
+        std::time::Duration::from_millis(self.backoff.saturating_mul(factor))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+/// Represents a rule rewriting the URIs of a protocol
+///
+/// A rule applies to the URIs matching `match`, or to every URI when it is not
+/// given.  The regular expression replacement is done first, then the
+/// components are changed.  When the scheme changes, the URI is handled by the
+/// protocol of the new scheme.
+pub struct RewriteRule {
+    /// Regular expression the URI must match for the rule to apply
+    #[serde(default, rename = "match", skip_serializing_if = "Option::is_none")]
+    pub matches : Option<String>,
+    /// Replacement of the text matched by `match`, e.g. `snip://$1`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replace : Option<String>,
+    /// The new scheme of the URI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scheme : Option<String>,
+    /// The new subcommand, the host part, of the URI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subcommand : Option<String>,
+    /// Query parameters to rename, from the old name to the new name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub rename : BTreeMap<String, String>,
+    /// Query parameters to add when the URI does not have them
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub defaults : BTreeMap<String, String>,
+}
+
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[derive(Default)]
+/// Represents a route, handling a subcommand of a protocol differently
+///
+/// The sections given in the route replace those of the protocol.  The route
+/// named `*` handles the subcommands that no other route handles; without it
+/// they are handled by the protocol itself.  See the `routing` module for the
+/// path patterns and conditions.
+pub struct RouteConfig {
+    /// The subcommand handled by the route, or `*`
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub subcommand : String,
+    /// The path pattern handled by the route, starting with the subcommand,
+    /// e.g. `/open/{notebook}/{*path}`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path : Option<String>,
+    /// The condition on the query parameters of the URIs handled by the route
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when : Option<Predicate>,
+    /// A short description of the route
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub desc : String,
+    /// The kind of handler, defaults to the kind of the protocol
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind : Option<HandlerKind>,
+    /// The script to call, for the `script` kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub script : Option<ProtocolScriptConfig>,
+    /// The shell to use when calling the script, for the `script` kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shell : Option<ProtocolShellConfig>,
+    /// The program to run, for the `exec` kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub exec : Option<ExecConfig>,
+    /// The file to append to, for the `append-file` kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub append_file : Option<AppendFileConfig>,
+    /// The endpoint to send the payload to, for the `http` kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http : Option<HttpConfig>,
+    /// The URI to dispatch instead, for the `forward` kind
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub forward : Option<ForwardConfig>,
+    /// The path pattern and regular expressions of the route, compiled by
+    /// `Config::compile_routes`
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub compiled : Option<CompiledRoute>,
+}
+
+impl RouteConfig {
+    /// Returns the name of the route, its path pattern or its subcommand,
+    /// followed by its condition
+    #[must_use] pub fn name(&self) -> String {
+        let name = self.path.as_deref().unwrap_or(&self.subcommand);
+        match &self.when {
+            Some(when) if name.is_empty() => format!("when {when}"),
+            Some(when) => format!("{name} when {when}"),
+            None => name.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq, JsonSchema)]
+#[serde(untagged, deny_unknown_fields)]
+/// Represents a condition on the query parameters of a URI
+pub enum Predicate {
+    /// The parameter has the value
+    Equals {
+        /// The name of the parameter
+        param : String,
+        /// The value of the parameter
+        equals : String,
+    },
+    /// The parameter matches the regular expression
+    Regex {
+        /// The name of the parameter
+        param : String,
+        /// The regular expression
+        regex : String,
+    },
+    /// The parameter is given, even if empty
+    Exists {
+        /// The name of the parameter
+        exists : String,
+    },
+    /// The condition does not hold
+    Not {
+        /// The condition
+        not : Box<Predicate>,
+    },
+    /// Every condition holds
+    And {
+        /// The conditions
+        and : Vec<Predicate>,
+    },
+    /// At least one of the conditions holds
+    Or {
+        /// The conditions
+        or : Vec<Predicate>,
+    },
+}
+
+impl fmt::Display for Predicate {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let join = |predicates : &[Predicate], op : &str| {
+            predicates.iter().map(ToString::to_string).collect::<Vec<String>>().join(op)
+        };
+        match self {
+            Self::Equals { param, equals } => write!(f, "{param} == {equals:?}"),
+            Self::Regex { param, regex } => write!(f, "{param} =~ /{regex}/"),
+            Self::Exists { exists } => write!(f, "exists {exists}"),
+            Self::Not { not } => write!(f, "not ({not})"),
+            Self::And { and } => write!(f, "({})", join(and, " and ")),
+            Self::Or { or } => write!(f, "({})", join(or, " or ")),
+        }
+    }
+}
+
+impl ProtocolConfig {
+    /// Returns the most specific route handling the URI, if there is one
+    #[must_use] pub fn route(&self, parsed : &ParsedUri) -> Option<RouteMatch<'_>> {
+        routing::find(&self.routes, parsed)
+    }
+
+    /// Returns the configuration of the protocol with the sections of the
+    /// route
+    #[must_use] pub fn with_route(&self, route : &RouteConfig) -> ProtocolConfig {
+        let mut config = self.clone();
+        config.routes = Vec::new();
+        if let Some(kind) = route.kind {
+            config.kind = kind;
+        }
+        if let Some(script) = &route.script {
+            config.script = script.clone();
+        }
+        if let Some(shell) = &route.shell {
+            config.shell = shell.clone();
+        }
+        config.exec = route.exec.clone().or(config.exec);
+        config.append_file = route.append_file.clone().or(config.append_file);
+        config.http = route.http.clone().or(config.http);
+        config.forward = route.forward.clone().or(config.forward);
+        config
+    }
+}
+
+
 // endregion Protocols config
 // --------------------------------------------------------------------------------
@@ -0,0 +1,191 @@
+//! The resident daemon, handling the URIs sent to it on a Unix socket.
+//!
+//! The daemon keeps the configuration loaded, so that a click only pays for
+//! starting the client.  Each connection carries one JSON request, on one
+//! line, and one JSON reply.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use resolve_path::PathResolveExt;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use simplelog::{info, warn};
+
+use crate::config::Config;
+use crate::error::ProtoHandlerError;
+
+const SOCKET_FILE: &str = "protohandler.sock";
+
+/// How long the daemon waits for a client to send its request
+const REQUEST_TIMEOUT : Duration = Duration::from_secs(5);
+
+/// A request sent to the daemon
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "request", rename_all = "lowercase")]
+pub enum Request {
+    /// Handle the URIs, replying with their results
+    Open {
+        /// The URIs to handle
+        uris : Vec<String>,
+    },
+    /// Reply with the status of the daemon
+    Status,
+    /// Stop the daemon once the URIs being handled are done
+    Stop,
+}
+
+/// The status of a running daemon
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct Status {
+    /// The process id of the daemon
+    pub pid : u32,
+    /// The path of the socket
+    pub socket : String,
+}
+
+/// Returns the path of the socket of the daemon
+#[must_use] pub fn socket_path(config : &Config) -> PathBuf {
+    match &config.daemon.socket {
+        Some(path) => path.resolve().into_owned(),
+        None => config.get_runtime_directory().join(SOCKET_FILE),
+    }
+}
+
+/// Runs the daemon until it is sent a `Stop` request.
+///
+/// # Arguments
+///
+/// * `path` - The path of the socket.
+/// * `handle` - Handles the URIs of an `Open` request, returning the results
+///   sent back to the client.
+///
+/// # Errors
+///
+/// This function will return an error if another daemon is listening on the
+/// socket, or if the socket cannot be created.
+pub fn serve<R, F>(path : &Path, handle : F) -> Result<(), ProtoHandlerError>
+where
+    R : Serialize,
+    F : Fn(Vec<String>) -> Vec<R> + Sync,
+{
+    let err = |e : io::Error| ProtoHandlerError::DaemonError {
+        path : path.display().to_string(),
+        reason : e.to_string(),
+    };
+    if UnixStream::connect(path).is_ok() {
+        return Err(err(io::Error::new(io::ErrorKind::AddrInUse, "a daemon is already running")));
+    }
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(err(e)),
+        _ => {},
+    }
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(err)?;
+    }
+    let listener = UnixListener::bind(path).map_err(err)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600)).map_err(err)?;
+    info!("Daemon listening on {}", path.display());
+
+    let status = Status { pid : std::process::id(), socket : path.display().to_string() };
+    let stopping = AtomicBool::new(false);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            if stopping.load(Ordering::SeqCst) {
+                break;
+            }
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Could not accept a connection: {e}");
+                    continue;
+                },
+            };
+            let (handle, status, stopping) = (&handle, &status, &stopping);
+            // Read in the thread, so that a slow client does not hold up the others
+            scope.spawn(move || {
+                let reply = match read_request(&stream) {
+                    Ok(Request::Open { uris }) => write_reply(&mut stream, &handle(uris)),
+                    Ok(Request::Status) => write_reply(&mut stream, status),
+                    Ok(Request::Stop) => {
+                        info!("Daemon stopping");
+                        stopping.store(true, Ordering::SeqCst);
+                        let reply = write_reply(&mut stream, status);
+                        // Wakes up the loop waiting for a connection
+                        let _ = UnixStream::connect(path);
+                        reply
+                    },
+                    Err(e) => {
+                        warn!("Could not read the request: {e}");
+                        return;
+                    },
+                };
+                if let Err(e) = reply {
+                    warn!("Could not reply to the client: {e}");
+                }
+            });
+        }
+    });
+    fs::remove_file(path).map_err(err)
+}
+
+/// Reads the request of a client
+fn read_request(stream : &UnixStream) -> io::Result<Request> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    serde_json::from_str(&line).map_err(io::Error::other)
+}
+
+/// Writes a reply to a client
+fn write_reply<T : Serialize>(stream : &mut UnixStream, reply : &T) -> io::Result<()> {
+    let mut line = serde_json::to_string(reply).map_err(io::Error::other)?;
+    line.push('\n');
+    stream.write_all(line.as_bytes())
+}
+
+/// Connects to the daemon.
+///
+/// # Errors
+///
+/// This function will return an error if the daemon is not running.
+pub fn connect(path : &Path) -> Result<UnixStream, ProtoHandlerError> {
+    UnixStream::connect(path).map_err(|e| ProtoHandlerError::DaemonError {
+        path : path.display().to_string(),
+        reason : e.to_string(),
+    })
+}
+
+/// Sends a request to the daemon and waits for its reply, for at most
+/// `timeout` unless it is zero.
+///
+/// # Errors
+///
+/// This function will return an error if the request cannot be sent, or if
+/// the daemon does not reply, `DaemonTimeout` if it does not reply in time.
+pub fn request<T : DeserializeOwned>(mut stream : UnixStream, request : &Request, timeout : Duration) -> Result<T, ProtoHandlerError> {
+    let path = stream
+        .peer_addr()
+        .ok()
+        .and_then(|a| a.as_pathname().map(|p| p.display().to_string()))
+        .unwrap_or_default();
+    let err = |e : io::Error| ProtoHandlerError::DaemonError { path : path.clone(), reason : e.to_string() };
+
+    stream.set_read_timeout(Some(timeout).filter(|t| !t.is_zero())).map_err(err)?;
+    write_reply(&mut stream, request).map_err(err)?;
+    let mut line = String::new();
+    BufReader::new(&stream).read_line(&mut line).map_err(|e| match e.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => ProtoHandlerError::DaemonTimeout { path : path.clone() },
+        _ => err(e),
+    })?;
+    if line.is_empty() {
+        return Err(err(io::Error::new(io::ErrorKind::UnexpectedEof, "the daemon closed the connection")));
+    }
+    serde_json::from_str(&line).map_err(|e| err(io::Error::other(e)))
+}
@@ -0,0 +1,159 @@
+//! Skipping of the URIs received twice in a short time.
+//!
+//! Some browsers and extensions open the same URI twice.  The URIs received
+//! recently are kept in a small state file, shared by the protoHandler
+//! processes, so that an identical URI received within the `dedupe_window` of
+//! its protocol is skipped.  Only a hash of the canonical URI is stored.
+
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+use simplelog::warn;
+
+use crate::concurrency::fnv1a;
+use crate::config::{Config, ProtocolConfig};
+use crate::error::ProtoHandlerError;
+use crate::uri::ParsedUri;
+
+const STATE_FILE: &str = "dedupe.json";
+
+/// When a URI was received, and until when identical URIs are skipped
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+struct Seen {
+    received : u64,
+    expires : u64,
+}
+
+/// Returns the path of the state file
+#[must_use] pub fn state_path(config : &Config) -> PathBuf {
+    config.get_state_directory().join(STATE_FILE)
+}
+
+/// Returns the canonical form of the URI, so that URIs differing only in the
+/// case of the scheme, the encoding of their components, a trailing `/` or
+/// the order of their parameters are identical.
+///
+/// A URI that cannot be parsed is its own canonical form.
+///
+/// # Examples
+///
+/// ```
+/// use protohandlers::dedupe::canonicalize;
+///
+/// assert_eq!(
+///     canonicalize("Snip-Proto://capture/?url=a%2Fb&title=x"),
+///     canonicalize("snip-proto://capture?title=x&url=a/b"),
+/// );
+/// ```
+#[must_use] pub fn canonicalize(uri : &str) -> String {
+    let lowered = match uri.split_once("://") {
+        Some((scheme, rest)) => format!("{}://{rest}", scheme.to_ascii_lowercase()),
+        None => uri.to_string(),
+    };
+    match ParsedUri::parse(&lowered) {
+        Ok(mut parsed) => {
+            parsed.path.truncate(parsed.path.trim_end_matches('/').len());
+            parsed.query.sort_by(|a, b| (&a.name, &a.value).cmp(&(&b.name, &b.value)));
+            parsed.to_string()
+        },
+        Err(_) => lowered,
+    }
+}
+
+/// Records that the URI was received, returning how many seconds ago an
+/// identical URI was received if it is within the `dedupe_window` of the
+/// protocol.
+///
+/// Nothing is recorded when the window is 0.  The URI is recorded before it
+/// is handled, so that an identical URI received meanwhile is skipped; call
+/// `forget` if it could not be handled.
+///
+/// # Errors
+///
+/// This function will return an error if the state file cannot be read or
+/// written.
+pub fn check(protocol : &ProtocolConfig, uri : &str, config : &Config) -> Result<Option<u64>, ProtoHandlerError> {
+    if protocol.dedupe_window == 0 {
+        return Ok(None);
+    }
+    let key = key(protocol, uri);
+    with_state(config, |seen, now| {
+        if let Some(previous) = seen.get(&key) {
+            return Some(now.saturating_sub(previous.received));
+        }
+        seen.insert(key, Seen { received : now, expires : now + protocol.dedupe_window });
+        None
+    })
+}
+
+/// Removes the URI recorded by `check`, so that an identical URI is not
+/// skipped.
+///
+/// # Errors
+///
+/// This function will return an error if the state file cannot be read or
+/// written.
+pub fn forget(protocol : &ProtocolConfig, uri : &str, config : &Config) -> Result<(), ProtoHandlerError> {
+    if protocol.dedupe_window == 0 {
+        return Ok(());
+    }
+    let key = key(protocol, uri);
+    with_state(config, |seen, _| {
+        seen.remove(&key);
+    })
+}
+
+/// Returns the key of the URI in the state file
+fn key(protocol : &ProtocolConfig, uri : &str) -> String {
+    format!("{:016x}", fnv1a(&format!("{}\n{}", protocol.name, canonicalize(uri))))
+}
+
+/// Updates the URIs received recently while holding a lock on the state file
+fn with_state<T>(config : &Config, f : impl FnOnce(&mut BTreeMap<String, Seen>, u64) -> T) -> Result<T, ProtoHandlerError> {
+    let path = state_path(config);
+    let err = |e : io::Error| ProtoHandlerError::StateError {
+        path : path.display().to_string(),
+        reason : e.to_string(),
+    };
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(err)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(&path)
+        .map_err(err)?;
+    file.lock().map_err(err)?;
+    let result = update(&mut file, f);
+    file.unlock().map_err(err)?;
+    result.map_err(err)
+}
+
+/// Updates the state file, which must be locked, dropping the expired URIs
+fn update<T>(file : &mut File, f : impl FnOnce(&mut BTreeMap<String, Seen>, u64) -> T) -> io::Result<T> {
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let mut seen : BTreeMap<String, Seen> = if content.trim().is_empty() {
+        BTreeMap::new()
+    } else {
+        serde_json::from_str(&content).unwrap_or_else(|e| {
+            warn!("Ignoring the invalid dedupe state: {e}");
+            BTreeMap::new()
+        })
+    };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    seen.retain(|_, s| s.expires > now);
+    let result = f(&mut seen, now);
+
+    file.set_len(0)?;
+    file.rewind()?;
+    file.write_all(serde_json::to_string(&seen).map_err(io::Error::other)?.as_bytes())?;
+    Ok(result)
+}
@@ -14,6 +14,84 @@ pub enum ProtoHandlerError {
     #[error("Shell {sh} not configured")]
     ShellNotConfigured { sh : String },
 
-    #[error("Could not parse config file '{path}'")]
-    ConfigParseError { path : String }
+    #[error("Could not parse config file '{path}': {reason}")]
+    ConfigParseError { path : String, reason : String },
+
+    #[error("Could not write config file '{path}'")]
+    ConfigWriteError { path : String },
+
+    #[error("Could not open log file '{path}': {reason}")]
+    LogError { path : String, reason : String },
+
+    #[error("Invalid time format '{format}': {reason}")]
+    TimeFormatError { format : String, reason : String },
+
+    #[error("Could not write audit log '{path}': {reason}")]
+    AuditError { path : String, reason : String },
+
+    #[error("Could not access history '{path}': {reason}")]
+    HistoryError { path : String, reason : String },
+
+    #[error("No history entry matches '{id}'")]
+    HistoryEntryNotFound { id : String },
+
+    #[error("More than one history entry matches '{id}'")]
+    HistoryEntryAmbiguous { id : String },
+
+    #[error("History entry '{id}' holds a redacted URI and cannot be replayed, set `history.raw_uris` to keep the URIs")]
+    HistoryEntryRedacted { id : String },
+
+    #[error("Could not access the dead-letter queue '{path}': {reason}")]
+    QueueError { path : String, reason : String },
+
+    #[error("No queued URI matches '{id}'")]
+    QueueEntryNotFound { id : String },
+
+    #[error("More than one queued URI matches '{id}'")]
+    QueueEntryAmbiguous { id : String },
+
+    #[error("Could not show the notification: {reason}")]
+    NotifyError { reason : String },
+
+    #[error("Invalid native message: {reason}")]
+    NativeMessageError { reason : String },
+
+    #[error("Could not write the native-messaging manifest '{path}': {reason}")]
+    ManifestError { path : String, reason : String },
+
+    #[error("Could not run '{cmd}': {reason}")]
+    SpawnError { cmd : String, reason : String },
+
+    #[error("Protocol {proto} has no '{kind}' section")]
+    HandlerNotConfigured { proto : String, kind : String },
+
+    #[error("Protocol {proto} is handled by '{kind}', which does not run a command")]
+    NotACommand { proto : String, kind : String },
+
+    #[error("Could not append to '{path}': {reason}")]
+    AppendError { path : String, reason : String },
+
+    #[error("Could not send to '{url}': {reason}")]
+    HttpError { url : String, reason : String },
+
+    #[error("Forwarding loop detected at '{uri}'")]
+    ForwardLoop { uri : String },
+
+    #[error("Invalid rewrite rule '{pattern}': {reason}")]
+    RewriteError { pattern : String, reason : String },
+
+    #[error("Invalid route pattern '{pattern}': {reason}")]
+    RouteError { pattern : String, reason : String },
+
+    #[error("Could not reach the daemon on '{path}': {reason}")]
+    DaemonError { path : String, reason : String },
+
+    #[error("The daemon on '{path}' did not reply in time")]
+    DaemonTimeout { path : String },
+
+    #[error("Could not lock '{path}': {reason}")]
+    LockError { path : String, reason : String },
+
+    #[error("Could not update the state in '{path}': {reason}")]
+    StateError { path : String, reason : String },
 }
@@ -0,0 +1,517 @@
+//! Explain how a URI would be handled, without running anything.
+//!
+//! The explanation walks through the same steps as `build_command`, recording
+//! the outcome of each one so that a misbehaving link can be diagnosed from
+//! the commandline.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use resolve_path::PathResolveExt;
+use serde::Serialize;
+
+use crate::config::{ConcurrencyMode, Config, FallbackAction, HandlerKind, ProtocolConfig};
+pub use crate::runner::PolicyCheck;
+use crate::rewrite;
+use crate::routing::{self, Capture, Condition, PathPattern};
+use crate::runner::{
+    build_command, get_protocol, handler_for, lookup_protocol, lookup_route, lookup_shell, CATCH_ALL, MAX_FORWARDS,
+};
+use crate::uri::ParsedUri;
+
+/// Whether a configured protocol matched the URI, and why
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct ProtocolMatch {
+    /// Name of the configured protocol
+    pub name : String,
+    /// True if this protocol handles the URI
+    pub matched : bool,
+    /// Why the protocol did or did not match
+    pub reason : String,
+}
+
+/// An environment variable set or removed for the command
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct EnvChange {
+    /// Name of the variable
+    pub name : String,
+    /// The new value, or `None` if the variable is removed
+    pub value : Option<String>,
+}
+
+/// Everything protoHandler would do with a URI
+#[derive(Debug, Clone, Serialize, PartialEq, Default)]
+pub struct Explanation {
+    /// The URI as it was given
+    pub uri : String,
+    /// The protocol found by `get_protocol`
+    pub protocol : Option<String>,
+    /// The components of the URI
+    pub parsed : Option<ParsedUri>,
+    /// Each configured protocol and whether it matched
+    pub protocols : Vec<ProtocolMatch>,
+    /// The URIs produced by the rewrite rules, in order
+    pub rewrites : Vec<String>,
+    /// The route within the protocol that handles the URI
+    pub route : Option<String>,
+    /// The values captured by the path pattern of the route
+    pub captures : Vec<Capture>,
+    /// The outcome of the conditions of the routes of the protocol
+    pub conditions : Vec<Condition>,
+    /// The kind of handler of the protocol
+    pub kind : Option<HandlerKind>,
+    /// What a built-in handler would do with the URI
+    pub action : Option<String>,
+    /// The name of the shell used to run the script
+    pub shell : Option<String>,
+    /// The script that would be run
+    pub script : Option<String>,
+    /// The program and arguments of the command
+    pub argv : Vec<String>,
+    /// Changes to the environment of the command
+    pub env : Vec<EnvChange>,
+    /// Checks that would pass or fail when running the command
+    pub checks : Vec<PolicyCheck>,
+    /// The error that would stop the URI from being handled
+    pub error : Option<String>,
+}
+
+impl Explanation {
+    /// Returns true if the URI would be handled without error
+    #[must_use] pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.checks.iter().all(|c| c.passed)
+    }
+}
+
+/// Explains how the given URI would be handled with the given configuration.
+///
+/// # Arguments
+///
+/// * `uri` - A string slice representing the URI.
+/// * `config` - A reference to the configuration object.
+///
+/// # Returns
+///
+/// * `Explanation` - The outcome of each step of handling the URI.
+#[must_use] pub fn explain(uri : &str, config : &Config) -> Explanation {
+    explain_at(uri, config, 0)
+}
+
+/// Explains the URI, which was forwarded `depth` times by rewrite rules
+fn explain_at(uri : &str, config : &Config, depth : usize) -> Explanation {
+    let mut explanation = Explanation {
+        uri : uri.to_string(),
+        ..Explanation::default()
+    };
+
+    explanation.parsed = ParsedUri::parse(uri).ok();
+    let Some(proto) = get_protocol(uri, config) else {
+        explanation.error = Some(format!("Could not determine protocol in uri: '{uri}'"));
+        return explanation;
+    };
+    explanation.protocol = Some(proto.clone());
+
+    explanation.protocols = config
+        .protocols
+        .iter()
+        .map(|p| {
+            let catch_all = p.name == CATCH_ALL && !config.protocols.iter().any(|p| p.name == proto);
+            let matched = p.name == proto || catch_all;
+            let reason = if catch_all {
+                format!("catch-all handles scheme '{proto}'")
+            } else if matched {
+                format!("name matches scheme '{proto}'")
+            } else {
+                format!("name '{}' does not match scheme '{proto}'", p.name)
+            };
+            ProtocolMatch {
+                name : p.name.clone(),
+                matched,
+                reason,
+            }
+        })
+        .collect();
+
+    let Some(protocol_config) = lookup_protocol(&proto, config) else {
+        let fallback = &config.fallback;
+        explanation.action = match fallback.action {
+            FallbackAction::Log => None,
+            FallbackAction::Notify => Some(String::from("show a notification")),
+            FallbackAction::Open => Some(format!("hand off to {}", fallback.open_program)),
+            FallbackAction::Forward => fallback.uri.as_ref().map(|uri| format!("forward to {uri}")),
+            FallbackAction::Exit => Some(format!("exit with {}", fallback.exit_code)),
+        };
+        if fallback.action != FallbackAction::Exit || fallback.exit_code != 0 {
+            explanation.error = Some(format!("{proto} Protocol not configured"));
+        }
+        return explanation;
+    };
+    explanation.parsed = ParsedUri::parse_as(uri, protocol_config.parser).ok();
+
+    let uri = match rewrite::apply(uri, &protocol_config.rewrite, protocol_config.parser) {
+        Ok(rewritten) if rewritten != uri => {
+            explanation.rewrites.push(rewritten.clone());
+            if get_protocol(&rewritten, config).as_ref() != Some(&proto) {
+                return explain_forward(explanation, &rewritten, config, depth);
+            }
+            rewritten
+        },
+        Ok(_) => uri.to_string(),
+        Err(e) => {
+            explanation.error = Some(e.to_string());
+            return explanation;
+        },
+    };
+    let uri = uri.as_str();
+
+    if let Ok(parsed) = ParsedUri::parse_as(uri, protocol_config.parser) {
+        explanation.conditions = protocol_config.routes.iter().flat_map(|r| routing::conditions(r, &parsed)).collect();
+    }
+    let (protocol_config, route) = lookup_route(protocol_config, uri);
+    explanation.route = route;
+    explanation.captures.clone_from(&protocol_config.captures);
+    explanation.kind = Some(protocol_config.kind);
+    explanation.checks.extend(kind_checks(&protocol_config, config));
+    explanation.checks.extend(policy_checks(&protocol_config));
+    match protocol_config.kind {
+        HandlerKind::Script => {
+            explanation.shell = Some(protocol_config.shell.name.clone());
+            explanation.script = Some(protocol_config.script.name.clone());
+        },
+        HandlerKind::Exec => {},
+        _ => {
+            match handler_for(&protocol_config).and_then(|h| h.describe(uri, config)) {
+                Ok(action) => explanation.action = Some(action),
+                Err(e) => explanation.error = Some(e.to_string()),
+            }
+            return explanation;
+        },
+    }
+
+    match build_command(uri.to_string(), config) {
+        Ok(command) => {
+            explanation.argv = std::iter::once(command.get_program())
+                .chain(command.get_args())
+                .map(|a| a.to_string_lossy().into_owned())
+                .collect();
+            explanation.env = command
+                .get_envs()
+                .map(|(k, v)| EnvChange {
+                    name : k.to_string_lossy().into_owned(),
+                    value : v.map(|v| v.to_string_lossy().into_owned()),
+                })
+                .collect();
+        },
+        Err(e) => explanation.error = Some(e.to_string()),
+    }
+
+    explanation
+}
+
+/// Continues the explanation with the URI a rewrite rule forwarded it to
+fn explain_forward(mut explanation : Explanation, uri : &str, config : &Config, depth : usize) -> Explanation {
+    if depth >= MAX_FORWARDS {
+        explanation.error = Some(format!("Forwarding loop detected at '{uri}'"));
+        return explanation;
+    }
+    let forwarded = explain_at(uri, config, depth + 1);
+    if forwarded.rewrites.iter().any(|r| r == &explanation.uri) {
+        explanation.error = Some(format!("Forwarding loop detected at '{}'", explanation.uri));
+        return explanation;
+    }
+    explanation.rewrites.extend(forwarded.rewrites);
+    Explanation {
+        uri : explanation.uri,
+        protocol : explanation.protocol,
+        parsed : explanation.parsed,
+        protocols : explanation.protocols,
+        rewrites : explanation.rewrites,
+        ..forwarded
+    }
+}
+
+/// Checks every configured protocol.
+///
+/// Verifies that the redaction patterns are valid, that protocol names are
+/// unique, that the rewrite and route patterns and the route conditions are
+/// valid, that every protocol refers to a configured shell, that the shell
+/// programs and scripts exist, and that built-in handlers are configured.
+///
+/// # Arguments
+///
+/// * `config` - A reference to the configuration object.
+///
+/// # Returns
+///
+/// * `Vec<PolicyCheck>` - The outcome of each check.
+#[must_use] pub fn check(config : &Config) -> Vec<PolicyCheck> {
+    let mut checks = Vec::new();
+    for pattern in &config.redaction.patterns {
+        let error = Regex::new(pattern).err();
+        checks.push(PolicyCheck {
+            name : String::from("redaction pattern"),
+            passed : error.is_none(),
+            detail : match error {
+                Some(e) => format!("'{pattern}' is not a valid regular expression: {e}"),
+                None => format!("'{pattern}' is valid"),
+            },
+        });
+    }
+    for (i, protocol) in config.protocols.iter().enumerate() {
+        let duplicate = config.protocols[..i].iter().any(|p| p.name == protocol.name);
+        checks.push(PolicyCheck {
+            name : format!("{}: unique name", protocol.name),
+            passed : !duplicate,
+            detail : if duplicate {
+                format!("protocol '{}' is configured more than once", protocol.name)
+            } else {
+                format!("protocol '{}' is configured once", protocol.name)
+            },
+        });
+        for rule in &protocol.rewrite {
+            if let Some(pattern) = &rule.matches {
+                let error = Regex::new(pattern).err();
+                checks.push(PolicyCheck {
+                    name : format!("{}: rewrite pattern", protocol.name),
+                    passed : error.is_none(),
+                    detail : match error {
+                        Some(e) => format!("'{pattern}' is not a valid regular expression: {e}"),
+                        None => format!("'{pattern}' is valid"),
+                    },
+                });
+            }
+        }
+        for mut c in kind_checks(protocol, config).into_iter().chain(policy_checks(protocol)) {
+            c.name = format!("{}: {}", protocol.name, c.name);
+            checks.push(c);
+        }
+        for route in &protocol.routes {
+            if let Some(when) = &route.when {
+                let error = routing::validate(when).err();
+                checks.push(PolicyCheck {
+                    name : format!("{}: route condition", protocol.name),
+                    passed : error.is_none(),
+                    detail : match error {
+                        Some(e) => e.to_string(),
+                        None => format!("'{when}' is valid"),
+                    },
+                });
+            }
+            if let Some(pattern) = &route.path {
+                let error = PathPattern::parse(pattern).err();
+                checks.push(PolicyCheck {
+                    name : format!("{}: route pattern", protocol.name),
+                    passed : error.is_none(),
+                    detail : match error {
+                        Some(e) => e.to_string(),
+                        None => format!("'{pattern}' is valid"),
+                    },
+                });
+            }
+            for mut c in kind_checks(&protocol.with_route(route), config) {
+                c.name = format!("{}/{}: {}", protocol.name, route.name(), c.name);
+                checks.push(c);
+            }
+        }
+    }
+    checks
+}
+
+/// Describes the policies limiting the URIs of the protocol handled
+fn policy_checks(protocol : &ProtocolConfig) -> Vec<PolicyCheck> {
+    let mut checks = Vec::new();
+    if protocol.dedupe_window > 0 {
+        checks.push(PolicyCheck {
+            name : String::from("dedupe"),
+            passed : true,
+            detail : format!("identical URIs are skipped for {} seconds", protocol.dedupe_window),
+        });
+    }
+    let concurrency = &protocol.concurrency;
+    let (passed, detail) = match concurrency.mode {
+        ConcurrencyMode::Unlimited => return checks,
+        ConcurrencyMode::Max if concurrency.max == 0 => (false, String::from("'max' must be at least 1")),
+        ConcurrencyMode::Max => (true, format!("at most {} URIs at the same time", concurrency.max)),
+        ConcurrencyMode::Serialize => (true, String::from("one URI at a time, the others wait")),
+        ConcurrencyMode::DropDuplicates => (
+            true,
+            format!("identical URIs are dropped for {} seconds", concurrency.window),
+        ),
+    };
+    checks.push(PolicyCheck { name : String::from("concurrency"), passed, detail });
+    checks
+}
+
+/// Checks the configuration of the kind of handler of the protocol
+fn kind_checks(protocol : &ProtocolConfig, config : &Config) -> Vec<PolicyCheck> {
+    match (protocol.kind, &protocol.exec) {
+        (HandlerKind::Script, _) => {
+            let mut checks = shell_checks(&protocol.shell.name, config);
+            checks.push(script_check(&protocol.script.name));
+            checks
+        },
+        (HandlerKind::Exec, Some(exec)) => {
+            let found = find_program(&exec.program);
+            vec![PolicyCheck {
+                name : String::from("program found"),
+                passed : found.is_some(),
+                detail : match found {
+                    Some(path) => format!("'{}' found at {}", exec.program, path.display()),
+                    None => format!("'{}' not found in PATH", exec.program),
+                },
+            }]
+        },
+        (kind, _) => {
+            let error = handler_for(protocol).err();
+            vec![PolicyCheck {
+                name : String::from("handler configured"),
+                passed : error.is_none(),
+                detail : match error {
+                    Some(e) => e.to_string(),
+                    None => format!("'{kind}' handler is configured"),
+                },
+            }]
+        },
+    }
+}
+
+/// Checks that the shell is configured and that its program can be found
+fn shell_checks(shell_name : &String, config : &Config) -> Vec<PolicyCheck> {
+    let Some(shell_config) = lookup_shell(shell_name, config) else {
+        return vec![PolicyCheck {
+            name : String::from("shell configured"),
+            passed : false,
+            detail : format!("shell '{shell_name}' is not listed in shells"),
+        }];
+    };
+    let found = find_program(&shell_config.cmd);
+    vec![
+        PolicyCheck {
+            name : String::from("shell configured"),
+            passed : true,
+            detail : format!("shell '{shell_name}' runs '{}'", shell_config.cmd),
+        },
+        PolicyCheck {
+            name : String::from("shell program found"),
+            passed : found.is_some(),
+            detail : match found {
+                Some(path) => format!("'{}' found at {}", shell_config.cmd, path.display()),
+                None => format!("'{}' not found in PATH", shell_config.cmd),
+            },
+        },
+    ]
+}
+
+/// Checks that the script exists
+fn script_check(script : &str) -> PolicyCheck {
+    let exists = Path::new(script).resolve().exists();
+    PolicyCheck {
+        name : String::from("script exists"),
+        passed : exists,
+        detail : if exists {
+            format!("'{script}' exists")
+        } else {
+            format!("'{script}' does not exist")
+        },
+    }
+}
+
+/// Finds the given program, either as a path or in the directories of `PATH`
+fn find_program(program : &str) -> Option<PathBuf> {
+    let path = Path::new(program);
+    if path.components().count() > 1 {
+        return path.exists().then(|| path.to_path_buf());
+    }
+    let dirs = std::env::var_os("PATH")?;
+    std::env::split_paths(&dirs).find_map(|dir| {
+        let candidate = dir.join(program);
+        if candidate.is_file() {
+            return Some(candidate);
+        }
+        let candidate = candidate.with_extension(std::env::consts::EXE_EXTENSION);
+        candidate.is_file().then_some(candidate)
+    })
+}
+
+impl fmt::Display for Explanation {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "URI: {}", self.uri)?;
+        if let Some(parsed) = &self.parsed {
+            writeln!(f, "  scheme:     {}", parsed.scheme)?;
+            writeln!(f, "  subcommand: {}", parsed.subcommand)?;
+            if !parsed.path.is_empty() {
+                writeln!(f, "  path:       {}", parsed.path)?;
+            }
+            for param in &parsed.query {
+                writeln!(f, "  param:      {} = {}", param.name, param.value)?;
+            }
+            if let Some(fragment) = &parsed.fragment {
+                writeln!(f, "  fragment:   {fragment}")?;
+            }
+        }
+
+        if !self.protocols.is_empty() {
+            writeln!(f, "Protocols:")?;
+            for p in &self.protocols {
+                let mark = if p.matched { "+" } else { "-" };
+                writeln!(f, "  {mark} {}: {}", p.name, p.reason)?;
+            }
+        }
+        for rewrite in &self.rewrites {
+            writeln!(f, "Rewritten to: {rewrite}")?;
+        }
+        if !self.conditions.is_empty() {
+            writeln!(f, "Route conditions:")?;
+            for c in &self.conditions {
+                let status = if c.passed { "pass" } else { "fail" };
+                if c.depth == 0 {
+                    writeln!(f, "  {}:", c.route)?;
+                }
+                writeln!(f, "  {}[{status}] {}", "  ".repeat(c.depth + 1), c.predicate)?;
+            }
+        }
+        if let Some(route) = &self.route {
+            writeln!(f, "Route: {route}")?;
+        }
+        for capture in &self.captures {
+            writeln!(f, "  captured:   {} = {}", capture.name, capture.value)?;
+        }
+        if let Some(kind) = &self.kind {
+            writeln!(f, "Handler: {kind}")?;
+        }
+        if let Some(action) = &self.action {
+            writeln!(f, "Action: {action}")?;
+        }
+        if let Some(shell) = &self.shell {
+            writeln!(f, "Shell: {shell}")?;
+        }
+        if let Some(script) = &self.script {
+            writeln!(f, "Script: {script}")?;
+        }
+        if !self.argv.is_empty() {
+            writeln!(f, "Command: {}", self.argv.join(" "))?;
+        }
+        if self.env.is_empty() {
+            writeln!(f, "Environment: unchanged")?;
+        } else {
+            writeln!(f, "Environment:")?;
+            for e in &self.env {
+                match &e.value {
+                    Some(v) => writeln!(f, "  {}={v}", e.name)?,
+                    None => writeln!(f, "  unset {}", e.name)?,
+                }
+            }
+        }
+        if !self.checks.is_empty() {
+            writeln!(f, "Checks:")?;
+            for c in &self.checks {
+                writeln!(f, "  {c}")?;
+            }
+        }
+        if let Some(error) = &self.error {
+            writeln!(f, "Error: {error}")?;
+        }
+        Ok(())
+    }
+}
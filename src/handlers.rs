@@ -0,0 +1,12 @@
+//! The built-in handlers, which handle URIs without running a script.
+//!
+//! Each handler implements `runner::Handler` and is selected by the `kind` of
+//! the protocol, see `runner::handler_for`.
+
+mod append_file;
+mod forward;
+mod http;
+
+pub use self::append_file::AppendFileHandler;
+pub use self::forward::ForwardHandler;
+pub use self::http::HttpHandler;
@@ -0,0 +1,65 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+
+use resolve_path::PathResolveExt;
+use simplelog::info;
+
+use crate::config::{AppendFileConfig, Config, UriParser};
+use crate::error::ProtoHandlerError;
+use crate::routing::Capture;
+use crate::runner::{Handler, Invocation, Outcome};
+use crate::template::{render, Context};
+use crate::uri::ParsedUri;
+
+/// Appends a templated entry to a file, for the `append-file` kind
+///
+/// The entry is written with a single write while holding an exclusive lock on
+/// the file, so that entries appended at the same time by other protoHandler
+/// processes do not interleave.
+pub struct AppendFileHandler {
+    /// The file and template of the protocol
+    pub config : AppendFileConfig,
+    /// How the URIs of the protocol are parsed
+    pub parser : UriParser,
+    /// The values captured by the path pattern of the route
+    pub captures : Vec<Capture>,
+}
+
+impl AppendFileHandler {
+    /// Returns the path of the file and the entry appended to it
+    fn render(&self, uri : &str) -> Result<(PathBuf, String), ProtoHandlerError> {
+        let parsed = ParsedUri::parse_as(uri, self.parser)?;
+        let context = Context::new(uri, &parsed).with_captures(&self.captures);
+        let path = PathBuf::from(render(&self.config.path, &context)).resolve().into_owned();
+        Ok((path, render(&self.config.template, &context)))
+    }
+}
+
+impl Handler for AppendFileHandler {
+    fn describe(&self, uri : &str, _config : &Config) -> Result<String, ProtoHandlerError> {
+        let (path, entry) = self.render(uri)?;
+        Ok(format!("append '{entry}' to {}", path.display()))
+    }
+
+    fn handle(&self, uri : &str, _invocation : &mut Invocation, _config : &Config) -> Result<Outcome, ProtoHandlerError> {
+        let (path, mut entry) = self.render(uri)?;
+        let err = |e : std::io::Error| ProtoHandlerError::AppendError {
+            path : path.display().to_string(),
+            reason : e.to_string(),
+        };
+
+        if !entry.ends_with('\n') {
+            entry.push('\n');
+        }
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir).map_err(err)?;
+        }
+        let mut file = OpenOptions::new().create(true).append(true).open(&path).map_err(err)?;
+        file.lock().map_err(err)?;
+        file.write_all(entry.as_bytes()).map_err(err)?;
+        file.unlock().map_err(err)?;
+        info!("Appended to {}", path.display());
+        Ok(Outcome::ok())
+    }
+}
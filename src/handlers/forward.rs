@@ -0,0 +1,34 @@
+use crate::config::{Config, ForwardConfig, UriParser};
+use crate::error::ProtoHandlerError;
+use crate::routing::Capture;
+use crate::runner::{forward, Handler, Invocation, Outcome};
+use crate::template::{render, Context};
+use crate::uri::ParsedUri;
+
+/// Rewrites the URI and dispatches it again, for the `forward` kind
+pub struct ForwardHandler {
+    /// The rewriting of the protocol
+    pub config : ForwardConfig,
+    /// How the URIs of the protocol are parsed
+    pub parser : UriParser,
+    /// The values captured by the path pattern of the route
+    pub captures : Vec<Capture>,
+}
+
+impl ForwardHandler {
+    /// Returns the URI to dispatch instead
+    fn target(&self, uri : &str) -> Result<String, ProtoHandlerError> {
+        let parsed = ParsedUri::parse_as(uri, self.parser)?;
+        Ok(render(&self.config.uri, &Context::new(uri, &parsed).with_captures(&self.captures)))
+    }
+}
+
+impl Handler for ForwardHandler {
+    fn describe(&self, uri : &str, _config : &Config) -> Result<String, ProtoHandlerError> {
+        Ok(format!("forward to {}", self.target(uri)?))
+    }
+
+    fn handle(&self, uri : &str, invocation : &mut Invocation, config : &Config) -> Result<Outcome, ProtoHandlerError> {
+        forward(&self.target(uri)?, invocation, config)
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::thread;
+use std::time::Duration;
+
+use serde::Serialize;
+use simplelog::{info, warn};
+
+use crate::config::{Config, HttpBody, HttpConfig, UriParser};
+use crate::error::ProtoHandlerError;
+use crate::redact::redacted;
+use crate::routing::Capture;
+use crate::runner::{Handler, Invocation, Outcome};
+use crate::template::{render, Context};
+use crate::uri::ParsedUri;
+
+/// Sends the payload of the URI to an HTTP endpoint, for the `http` kind
+pub struct HttpHandler {
+    /// The endpoint of the protocol
+    pub config : HttpConfig,
+    /// How the URIs of the protocol are parsed
+    pub parser : UriParser,
+    /// The values captured by the path pattern of the route
+    pub captures : Vec<Capture>,
+}
+
+/// The JSON object sent to the endpoint when no fields are configured
+#[derive(Debug, Serialize)]
+struct Payload<'a> {
+    uri : &'a str,
+    scheme : &'a str,
+    subcommand : &'a str,
+    path : &'a str,
+    query : BTreeMap<&'a str, &'a str>,
+    fragment : Option<&'a str>,
+}
+
+impl<'a> Payload<'a> {
+    fn new(uri : &'a str, parsed : &'a ParsedUri) -> Self {
+        Self {
+            uri,
+            scheme : &parsed.scheme,
+            subcommand : &parsed.subcommand,
+            path : &parsed.path,
+            query : parsed.query.iter().map(|p| (p.name.as_str(), p.value.as_str())).collect(),
+            fragment : parsed.fragment.as_deref(),
+        }
+    }
+}
+
+/// Maps the status of the response to the exit code of protoHandler
+fn exit_code(status : u16) -> i32 {
+    match status {
+        200..=299 => 0,
+        400..=499 => 4,
+        500..=599 => 5,
+        _ => 1,
+    }
+}
+
+impl HttpHandler {
+    /// Sends the request once, returning the status of the response or the
+    /// reason the endpoint could not be reached
+    fn send(&self, url : &str, headers : &[(String, String)], context : &Context) -> Result<u16, String> {
+        let agent = ureq::AgentBuilder::new()
+            .timeout(Duration::from_secs(self.config.timeout))
+            .build();
+        let mut request = agent.request(&self.config.method.to_string(), url);
+        for (name, value) in headers {
+            request = request.set(name, value);
+        }
+
+        let fields : Vec<(String, String)> = if self.config.fields.is_empty() {
+            context.parsed.query.iter().map(|p| (p.name.clone(), p.value.clone())).collect()
+        } else {
+            self.config
+                .fields
+                .iter()
+                .map(|(name, value)| (name.clone(), render(value, context)))
+                .collect()
+        };
+        let body = match self.config.body {
+            Some(body) => body,
+            None if self.config.method.has_body() => HttpBody::Json,
+            None => {
+                for (name, value) in &fields {
+                    request = request.query(name, value);
+                }
+                HttpBody::None
+            },
+        };
+        let response = match body {
+            HttpBody::Json if self.config.fields.is_empty() => request.send_json(Payload::new(context.uri, context.parsed)),
+            HttpBody::Json => request.send_json(fields.into_iter().collect::<BTreeMap<String, String>>()),
+            HttpBody::Form => {
+                let form : Vec<(&str, &str)> = fields.iter().map(|(n, v)| (n.as_str(), v.as_str())).collect();
+                request.send_form(&form)
+            },
+            HttpBody::None => request.call(),
+        };
+        match response {
+            Ok(response) => Ok(response.status()),
+            Err(ureq::Error::Status(status, _)) => Ok(status),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+impl Handler for HttpHandler {
+    fn describe(&self, uri : &str, _config : &Config) -> Result<String, ProtoHandlerError> {
+        let parsed = ParsedUri::parse_as(uri, self.parser)?;
+        let url = render(&self.config.url, &Context::new(uri, &parsed).with_captures(&self.captures));
+        Ok(format!("{} the payload to {url}", self.config.method))
+    }
+
+    fn handle(&self, uri : &str, invocation : &mut Invocation, _config : &Config) -> Result<Outcome, ProtoHandlerError> {
+        let parsed = ParsedUri::parse_as(uri, self.parser)?;
+        let context = Context::new(uri, &parsed).with_captures(&self.captures);
+        let url = render(&self.config.url, &context);
+        let headers : Vec<(String, String)> = self
+            .config
+            .headers
+            .iter()
+            .map(|(name, value)| (name.clone(), render(value, &context)))
+            .collect();
+        invocation.argv = vec![self.config.method.to_string(), url.clone()];
+
+        let mut attempt = 0;
+        loop {
+            info!("Sending {} request to {}", self.config.method, redacted(&url));
+            let result = self.send(&url, &headers, &context);
+            let retry = match &result {
+                Ok(status) => *status >= 500,
+                Err(_) => true,
+            };
+            if retry && attempt < self.config.retries {
+                attempt += 1;
+                warn!("Request failed, retrying ({attempt}/{})", self.config.retries);
+                thread::sleep(Duration::from_millis(self.config.retry_delay));
+                continue;
+            }
+            return match result {
+                Ok(status) => {
+                    info!("Endpoint answered {status}");
+                    let code = exit_code(status);
+                    Ok(if code == 0 { Outcome::ok() } else { Outcome::failed(code) })
+                },
+                Err(e) => Err(ProtoHandlerError::HttpError { url : redacted(&url), reason : redacted(&e) }),
+            };
+        }
+    }
+}
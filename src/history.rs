@@ -0,0 +1,228 @@
+//! The invocation history, a store of the recent URIs and their outcome.
+//!
+//! The history is an append-only file of JSON lines, kept in the state
+//! directory.  The URIs are redacted like in the logs, unless
+//! `history.raw_uris` is set so that any past invocation can be replayed: an
+//! entry whose URI was redacted is marked, and cannot be replayed.
+//!
+//! Processes append to the history while holding a lock on `<history>.lock`,
+//! the history itself being replaced when it is pruned.
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use resolve_path::PathResolveExt;
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+
+use crate::config::Config;
+use crate::error::ProtoHandlerError;
+use crate::redact::{redact, redacted};
+use crate::runner::Invocation;
+
+const HISTORY_FILE: &str = "history.jsonl";
+
+/// The smallest size of an entry, in bytes.  The history is not read to be
+/// pruned until it is larger than `max_entries` entries of this size.
+const MIN_ENTRY_SIZE : u64 = 128;
+
+/// One entry of the history
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct HistoryEntry {
+    /// The unique id of the invocation
+    pub id : String,
+    /// When the URI started being handled, in RFC 3339 format
+    pub timestamp : String,
+    /// The URI, redacted unless `history.raw_uris` is set
+    pub uri : String,
+    /// True if parts of the URI were redacted, so that it cannot be replayed
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub uri_redacted : bool,
+    /// The protocol found in the URI
+    pub protocol : Option<String>,
+    /// True if the command ran and exited successfully
+    pub success : bool,
+    /// The exit code of the command, if it ran to completion
+    pub exit_code : Option<i32>,
+    /// The error that stopped the URI from being handled
+    pub error : Option<String>,
+    /// How long handling the URI took, in milliseconds
+    pub duration_ms : u128,
+    /// The id of the entry this invocation replayed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub replay_of : Option<String>,
+}
+
+impl HistoryEntry {
+    /// Creates the history entry of an invocation
+    #[must_use] pub fn new(invocation : &Invocation) -> Self {
+        Self {
+            id : invocation.id.clone(),
+            timestamp : invocation.started.format(&Rfc3339).unwrap_or_default(),
+            uri : invocation.uri.clone(),
+            uri_redacted : false,
+            protocol : invocation.protocol.clone(),
+            success : invocation.success(),
+            exit_code : invocation.exit_code(),
+            error : invocation.error(),
+            duration_ms : invocation.duration.as_millis(),
+            replay_of : None,
+        }
+    }
+
+    /// Returns the entry with its URI and error redacted, to be shown
+    #[must_use] pub fn redacted(&self) -> Self {
+        Self {
+            uri : redacted(&self.uri),
+            error : self.error.as_deref().map(redacted),
+            ..self.clone()
+        }
+    }
+}
+
+impl fmt::Display for HistoryEntry {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.success { "ok" } else { "failed" };
+        write!(f, "{}  {}  [{status}]  {}", &self.id[..8.min(self.id.len())], self.timestamp, self.uri)
+    }
+}
+
+/// Returns the path of the history
+#[must_use] pub fn history_path(config : &Config) -> PathBuf {
+    match &config.history.path {
+        Some(path) => path.resolve().into_owned(),
+        None => config.get_state_directory().join(HISTORY_FILE),
+    }
+}
+
+/// Adds an entry to the history, if it is enabled.
+///
+/// The URI and error of the entry are redacted, unless `history.raw_uris` is
+/// set.  The oldest entries are removed once the history holds a quarter more
+/// than `history.max_entries`.
+///
+/// # Errors
+///
+/// This function will return an error if the history cannot be written.
+pub fn record(entry : &HistoryEntry, config : &Config) -> Result<(), ProtoHandlerError> {
+    if !config.history.enabled {
+        return Ok(());
+    }
+    let path = history_path(config);
+    let err = |e : io::Error| ProtoHandlerError::HistoryError {
+        path : path.display().to_string(),
+        reason : e.to_string(),
+    };
+
+    let entry = if config.history.raw_uris {
+        entry.clone()
+    } else {
+        let uri = redact(&entry.uri, &config.redaction);
+        HistoryEntry {
+            uri_redacted : uri != entry.uri,
+            uri,
+            error : entry.error.as_deref().map(|e| redact(e, &config.redaction)),
+            ..entry.clone()
+        }
+    };
+    let mut line = serde_json::to_string(&entry).map_err(|e| err(io::Error::other(e)))?;
+    line.push('\n');
+
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(err)?;
+    }
+    let lock = open_private(&sibling(&path, "lock")).map_err(err)?;
+    lock.lock().map_err(err)?;
+    let result = open_private(&path)
+        .and_then(|mut f| f.write_all(line.as_bytes()))
+        .and_then(|()| prune(&path, config.history.max_entries, &entry.id));
+    lock.unlock().map_err(err)?;
+    result.map_err(err)
+}
+
+/// Returns the path of a file next to the history, named after it
+fn sibling(path : &Path, extension : &str) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+/// Opens a file for appending, readable by the user only
+fn open_private(path : &Path) -> io::Result<std::fs::File> {
+    let mut options = OpenOptions::new();
+    options.create(true).append(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options.open(path)
+}
+
+/// Keeps only the last `max_entries` lines of the history, once it holds a
+/// quarter more.  The history must be locked.
+///
+/// The lines kept are written to a file named after the entry being recorded,
+/// which replaces the history.
+fn prune(path : &Path, max_entries : usize, id : &str) -> io::Result<()> {
+    let threshold = u64::try_from(max_entries).unwrap_or(u64::MAX).saturating_mul(MIN_ENTRY_SIZE);
+    if std::fs::metadata(path)?.len() <= threshold {
+        return Ok(());
+    }
+    let content = std::fs::read_to_string(path)?;
+    let lines : Vec<&str> = content.lines().collect();
+    if lines.len() <= max_entries + max_entries / 4 {
+        return Ok(());
+    }
+    let mut kept = lines[lines.len() - max_entries..].join("\n");
+    kept.push('\n');
+    let tmp = sibling(path, &format!("{id}.tmp"));
+    let written = open_private(&tmp)
+        .and_then(|mut f| f.write_all(kept.as_bytes()))
+        .and_then(|()| std::fs::rename(&tmp, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+    written
+}
+
+/// Loads the entries of the history, oldest first.
+///
+/// Lines that cannot be parsed are skipped.
+///
+/// # Errors
+///
+/// This function will return an error if the history exists but cannot be
+/// read.
+pub fn load(config : &Config) -> Result<Vec<HistoryEntry>, ProtoHandlerError> {
+    let path = history_path(config);
+    match std::fs::read_to_string(&path) {
+        Ok(content) => Ok(content
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(ProtoHandlerError::HistoryError {
+            path : path.display().to_string(),
+            reason : e.to_string(),
+        }),
+    }
+}
+
+/// Finds the entry whose id starts with the given prefix.
+///
+/// # Errors
+///
+/// This function will return an error if no entry, or more than one entry,
+/// matches the prefix.
+pub fn find<'a>(entries : &'a [HistoryEntry], id : &str) -> Result<&'a HistoryEntry, ProtoHandlerError> {
+    let mut matches = entries.iter().filter(|e| e.id.starts_with(id));
+    match (matches.next(), matches.next()) {
+        (Some(entry), None) => Ok(entry),
+        (None, _) => Err(ProtoHandlerError::HistoryEntryNotFound { id : id.to_string() }),
+        (Some(_), Some(_)) => Err(ProtoHandlerError::HistoryEntryAmbiguous { id : id.to_string() }),
+    }
+}
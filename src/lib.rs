@@ -0,0 +1,67 @@
+//! A tool to trigger a script based on the protocol and URI sent to the program
+//!
+//! Protocol handlers are a function found in linux, `MacOS` and Windows that
+//! allows the OS to associate a URI with a given program.  protoHandler.rs can
+//! be configured to receive the URI and pass them on to other programs or
+//! scripts based on the protocol.
+//!
+//! This library provides the configuration loading, URI parsing, routing,
+//! command building and execution used by the `protohandlers` program, so
+//! that they can be reused from other tools.
+//!
+//! # Examples
+//!
+//! ```
+//! use protohandlers::{build_command, Config, ParsedUri};
+//! use protohandlers::config::{ProtocolConfig, ProtocolScriptConfig, ProtocolShellConfig};
+//!
+//! let mut config = Config::new();
+//! config.protocols.push(ProtocolConfig {
+//!     name : String::from("snip-proto"),
+//!     desc : String::from("A web snipping protocol"),
+//!     script : ProtocolScriptConfig { name : String::from("capture.ps1"), args : Vec::new() },
+//!     shell : ProtocolShellConfig { name : String::from("pwsh"), args : Vec::new() },
+//!     ..ProtocolConfig::default()
+//! });
+//!
+//! let uri = ParsedUri::parse("snip-proto://capture?title=Get+started").unwrap();
+//! assert_eq!(Some("Get started"), uri.param("title"));
+//!
+//! let command = build_command(String::from("snip-proto://capture"), &config).unwrap();
+//! assert_eq!("pwsh", command.get_program());
+//! ```
+
+#![warn(clippy::pedantic)]
+
+pub mod audit;
+pub mod concurrency;
+pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod dedupe;
+pub mod error;
+pub mod explain;
+pub mod handlers;
+pub mod history;
+pub mod logging;
+pub mod native;
+pub mod notify;
+pub mod queue;
+pub mod redact;
+pub mod rewrite;
+pub mod routing;
+pub mod runner;
+pub mod template;
+pub mod uri;
+
+extern crate log;
+extern crate simplelog;
+
+pub use crate::config::Config;
+pub use crate::error::ProtoHandlerError;
+pub use crate::explain::{explain, Explanation};
+pub use crate::runner::{build_command, get_protocol, lookup_protocol, lookup_shell, run_command, Handler, Invocation, Outcome};
+pub use crate::uri::ParsedUri;
+
+#[cfg(test)]
+mod tests;
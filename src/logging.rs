@@ -0,0 +1,198 @@
+//! Initialization of the terminal and file loggers.
+//!
+//! The loggers are configured from the `logging` section of the
+//! configuration.  The log file is rotated when it has grown larger than
+//! `logging.max_size`, when protoHandler starts and while it writes to the
+//! file, so that a long running daemon does not grow it without bounds.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+
+use resolve_path::PathResolveExt;
+use simplelog::{
+    ColorChoice, CombinedLogger, ConfigBuilder, LevelFilter, SharedLogger, TermLogger,
+    TerminalMode, WriteLogger,
+};
+
+use crate::config::{LoggingConfig, TerminalLogging};
+use crate::error::ProtoHandlerError;
+
+impl TryFrom<&LoggingConfig> for simplelog::Config {
+    type Error = ProtoHandlerError;
+
+    fn try_from(logging : &LoggingConfig) -> Result<Self, Self::Error> {
+        let mut builder = ConfigBuilder::new();
+        builder
+            .set_thread_level(LevelFilter::from(&logging.thread))
+            .set_target_level(LevelFilter::from(&logging.target));
+
+        match logging.time_format.as_deref() {
+            None => {},
+            Some("rfc3339") => {
+                builder.set_time_format_rfc3339();
+            },
+            Some("rfc2822") => {
+                builder.set_time_format_rfc2822();
+            },
+            Some(format) => {
+                // simplelog only accepts a static format description.  The
+                // loggers live as long as the program, so leaking it is fine.
+                let format : &'static str = Box::leak(format.to_string().into_boxed_str());
+                let items = time::format_description::parse_borrowed::<1>(format).map_err(|e| {
+                    ProtoHandlerError::TimeFormatError { format : format.to_string(), reason : e.to_string() }
+                })?;
+                builder.set_time_format_custom(Box::leak(items.into_boxed_slice()));
+            },
+        }
+        Ok(builder.build())
+    }
+}
+
+/// The log file, rotated when it grows larger than `logging.max_size`
+pub(crate) struct LogFile {
+    path : PathBuf,
+    file : File,
+    /// The size of the file, counting what this process wrote to it
+    size : u64,
+    max_size : Option<u64>,
+    max_files : usize,
+    /// True if the last write ended a line, so that a message is not split
+    /// across two files
+    line_start : bool,
+}
+
+impl LogFile {
+    /// Rotates the log file and opens the new one.  Another process may have
+    /// rotated it already, in which case only the new one is opened.
+    fn reopen(&mut self, max_size : u64) -> io::Result<()> {
+        rotate(&self.path, max_size, self.max_files)?;
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = self.file.metadata()?.len();
+        Ok(())
+    }
+}
+
+impl Write for LogFile {
+    fn write(&mut self, buf : &[u8]) -> io::Result<usize> {
+        if let Some(max_size) = self.max_size {
+            if self.line_start && self.size >= max_size {
+                self.reopen(max_size)?;
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        if written > 0 {
+            self.line_start = buf[written - 1] == b'\n';
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Initializes the terminal and file loggers.
+///
+/// # Arguments
+///
+/// * `logging` - A reference to the logging configuration.
+///
+/// # Errors
+///
+/// This function will return an error if `logging.time_format` is invalid, if
+/// the log file cannot be rotated or opened, or if a logger was already
+/// initialized.
+pub fn init_log(logging : &LoggingConfig) -> Result<(), ProtoHandlerError> {
+    let log_config = simplelog::Config::try_from(logging)?;
+    let mut loggers : Vec<Box<dyn SharedLogger>> = Vec::new();
+
+    let terminal = match logging.terminal {
+        TerminalLogging::Auto => io::stderr().is_terminal(),
+        TerminalLogging::Always => true,
+        TerminalLogging::Never => false,
+    };
+    if terminal {
+        loggers.push(TermLogger::new(
+            logging.terminal_level(),
+            log_config.clone(),
+            TerminalMode::Mixed,
+            ColorChoice::Auto,
+        ));
+    }
+
+    let file_level = logging.file_level();
+    if file_level != LevelFilter::Off {
+        let log_path = logging.path.resolve().into_owned();
+        let file = open_log_file(&log_path, logging).map_err(|e| ProtoHandlerError::LogError {
+            path : log_path.display().to_string(),
+            reason : e.to_string(),
+        })?;
+        loggers.push(WriteLogger::new(file_level, log_config, file));
+    }
+
+    CombinedLogger::init(loggers).map_err(|e| ProtoHandlerError::LogError {
+        path : logging.path.clone(),
+        reason : e.to_string(),
+    })
+}
+
+/// Opens the log file, creating its directory and rotating it if needed
+pub(crate) fn open_log_file(path : &Path, logging : &LoggingConfig) -> io::Result<LogFile> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    if let Some(max_size) = logging.max_size {
+        rotate(path, max_size, logging.max_files)?;
+    }
+    let file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .append(logging.append)
+        .truncate(!logging.append)
+        .open(path)?;
+    Ok(LogFile {
+        path : path.to_path_buf(),
+        size : file.metadata()?.len(),
+        file,
+        max_size : logging.max_size,
+        max_files : logging.max_files,
+        line_start : true,
+    })
+}
+
+/// Rotates the log file if it is larger than `max_size` bytes.
+///
+/// The log file is renamed to `<path>.1`, `<path>.1` to `<path>.2` and so on,
+/// keeping at most `max_files` rotated files.
+///
+/// # Errors
+///
+/// This function will return an error if a file cannot be renamed or removed.
+pub fn rotate(path : &Path, max_size : u64, max_files : usize) -> io::Result<()> {
+    match std::fs::metadata(path) {
+        Ok(metadata) if metadata.len() >= max_size => {},
+        _ => return Ok(()),
+    }
+    if max_files == 0 {
+        return std::fs::remove_file(path);
+    }
+
+    let rotated = |n : usize| -> PathBuf {
+        let mut name = path.as_os_str().to_owned();
+        name.push(format!(".{n}"));
+        PathBuf::from(name)
+    };
+    let oldest = rotated(max_files);
+    if oldest.exists() {
+        std::fs::remove_file(oldest)?;
+    }
+    for n in (1..max_files).rev() {
+        let from = rotated(n);
+        if from.exists() {
+            std::fs::rename(from, rotated(n + 1))?;
+        }
+    }
+    std::fs::rename(path, rotated(1))
+}
@@ -1,152 +1,190 @@
 //! A tool to trigger a script based on the protocol and URI sent to the program
 //!
-//! Protocol handlers are a function found in linux, `MacOS` and Windows that
-//! allows the OS to associate a URI with a given program.  protoHandler.rs can
-//! be configured to receive the URI and pass them on to other programs or
-//! scripts based on the protocol.
+//! This is the commandline of protoHandler.rs, the handling of the URIs is
+//! provided by the `protohandlers` library.
 
 #![warn(clippy::pedantic)]
 
-pub mod cli;
-pub mod runner;
-pub mod config;
-pub mod error;
+mod cli;
+mod commands;
 
-#[cfg(test)]
-mod tests;
-
-use std::borrow::Cow;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-extern crate log;
-extern crate simplelog;
+use std::process::ExitCode;
 
-use std::ffi::OsStr;
-use std::fs::File;
-use std::process::Stdio;
-use std::vec;
-
-use clap::Parser;
-use runner::build_command;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser};
+use clap_complete::CompleteEnv;
 use resolve_path::PathResolveExt;
-use simplelog::CombinedLogger;
-use simplelog::{ColorChoice, TermLogger, TerminalMode, WriteLogger, error, info, warn};
 
-use crate::cli::Cli;
-use crate::config::Config;
+use protohandlers::config::{Config, TerminalLogging};
+#[cfg(unix)]
+use protohandlers::daemon::Request;
+use protohandlers::error::ProtoHandlerError;
+use protohandlers::logging::init_log;
+use protohandlers::{redact, runner};
 
-fn main() {
-    let mut config = Config::new();
+use crate::cli::{Cli, Command, ConfigCommand, DaemonCommand, HistoryCommand, LogCommand, NativeCommand, QueueCommand, COMPLETE_VAR};
+
+fn main() -> ExitCode {
+    CompleteEnv::with_factory(Cli::command).var(COMPLETE_VAR).complete();
 
     let args = match Cli::try_parse() {
-        Ok(args) => {
-            println!("Parsed commandline arguments");
-            args
-        },
+        Ok(args) => args,
         Err(e) => {
             let _ = e.print();
-            std::process::exit(1);
+            return if e.use_stderr() { ExitCode::FAILURE } else { ExitCode::SUCCESS };
         },
     };
 
-    if let Some(config_file) = args.config_file {
-        if Path::new(&config_file).resolve().exists() {
-                println!("Using {config_file:?} as config file");
-                match config.load(config_file) {
-                    Ok(()) => {
-                        println!("Successfully loaded config");
-                    },
-                    Err(e) => {
-                        panic!("Error loading config {e}");
-                    },
-                }
-        } else {
-            println!("{config_file:?} given as config file but does not exist");
-        }
-    } else {
-        let default_config = config.get_file();
-        if default_config.exists() {
-            match config.load(default_config.into_os_string().into_string().unwrap()) {
-                Ok(()) => {},
-                Err(e) => {
-                    panic!("Error loading config {e}");
-                }
-            }
-        }
+    match &args.command {
+        Some(Command::Config(ConfigCommand::Schema)) => {
+            println!("{}", Config::schema());
+            return ExitCode::SUCCESS;
+        },
+        Some(Command::Man { out_dir }) => return commands::man(out_dir.as_deref()),
+        Some(Command::Completions { shell }) => return commands::completions(*shell),
+        _ => {},
     }
 
+    let (mut config, config_path) = match load_config(args.config_file.as_ref()) {
+        Ok(loaded) => loaded,
+        Err(e) => {
+            eprintln!("{e}");
+            return ExitCode::FAILURE;
+        },
+    };
+
     if let Some(log_file) = args.log_file {
-        if Path::new(&log_file).exists() {
-                println!("Using {log_file:?} as log file");
-                config.logging.path = log_file;
-            } else {
-                println!("{log_file:?} given as log file but does not exist yet");
-            }
+        config.logging.path = log_file;
     }
 
-    init_log(&config);
-
-    match args.uri {
-        None => (),
-        Some(uri) => {
-            info!("Got uri, building command");
-
-            match build_command(uri, config) {
-                Ok(mut child) => {
-                    let a : Vec<&OsStr> = child.get_args().collect();
-                    let a = a
-                        .into_iter()
-                        .map(|o| o.to_string_lossy())
-                        .collect::<Vec<Cow<str>>>()
-                        .join(" ");
-                    info!("Arguments are {a}");
-                    child.stdout(Stdio::inherit());
-
-                    // Run the external cmd
-                    let result = child.spawn();
-
-                    match result {
-                        Ok(mut r) => {
-                            if let Some(stdout) = r.stdout.take() {
-                                info!("command output {:#?}", stdout);
-                            }
-                            match r.try_wait() {
-                                Ok(Some(status)) => info!("exited with: {status}"),
-                                Ok(None) => {
-                                    info!("status not ready yet, let's really wait");
-                                    let r = r.wait();
-                                    info!("result: {r:?}");
-                                }
-                                Err(e) => info!("error attempting to wait: {e}"),
-                            }
-                        },
-                        Err(_e) => {
-                            error!("Failed to run child process");
-                        }
-                    }
-                },
-                Err(e) => {
-                    panic!("Error spawning process {e}")
+    redact::set_config(&config.redaction);
+
+    let command = match args.command {
+        Some(_) if !args.uris.is_empty() => {
+            Cli::command()
+                .error(ErrorKind::ArgumentConflict, "a URI cannot be given before a subcommand")
+                .exit();
+        },
+        Some(command) => command,
+        None => {
+            let uris = args.uris.into_iter().chain(args.uri_flag).collect();
+            Command::Open { uris, dry_run : false, format : cli::OutputFormat::Human }
+        },
+    };
+
+    run(command, config, &config_path)
+}
+
+/// Carries out the command with the configuration loaded from `config_path`
+fn run(command : Command, mut config : Config, config_path : &Path) -> ExitCode {
+    match command {
+        Command::Open { uris, dry_run : true, format } => commands::explain_uris(&uris, &config, format),
+        Command::Explain { uri, format } => commands::explain_uris(&[uri], &config, format),
+        Command::Open { uris, format, .. } => {
+            if let Err(e) = init_log(&config.logging) {
+                eprintln!("{e}");
+            }
+            commands::open(uris, &config, format)
+        },
+        Command::Register(register) => commands::register(register, config, config_path),
+        Command::Unregister { name } => commands::unregister(&name, config, config_path),
+        Command::List { format } => commands::list(&config, format),
+        Command::Check { format } => commands::check_config(&config, format),
+        Command::Config(ConfigCommand::Path) => {
+            println!("{}", config_path.display());
+            ExitCode::SUCCESS
+        },
+        Command::Config(ConfigCommand::Show) => commands::show_config(&config),
+        Command::Log(LogCommand::Path) => {
+            println!("{}", config.logging.path.resolve().display());
+            ExitCode::SUCCESS
+        },
+        Command::Log(LogCommand::Show { lines }) => commands::show_log(&config, lines),
+        Command::History(HistoryCommand::List { protocol, failed, limit, format }) => {
+            commands::history_list(&config, protocol.as_deref(), failed, limit, format)
+        },
+        Command::History(HistoryCommand::Show { id, format }) => commands::history_show(&config, &id, format),
+        Command::History(HistoryCommand::Replay { id, with_config }) => {
+            if let Err(e) = init_log(&config.logging) {
+                eprintln!("{e}");
+            }
+            match with_config {
+                Some(file) => match load_config(Some(&file)) {
+                    Ok((run_config, _)) => commands::history_replay(&config, &id, &run_config),
+                    Err(e) => {
+                        eprintln!("{e}");
+                        ExitCode::FAILURE
+                    },
                 },
+                None => commands::history_replay(&config, &id, &config),
+            }
+        },
+        Command::Queue(QueueCommand::List { format }) => commands::queue_list(&config, format),
+        Command::Queue(QueueCommand::Retry { id, .. }) => {
+            if let Err(e) = init_log(&config.logging) {
+                eprintln!("{e}");
             }
+            commands::queue_retry(&config, id.as_deref())
         },
+        Command::Queue(QueueCommand::Purge { id, .. }) => commands::queue_purge(&config, id.as_deref()),
+        Command::Native(NativeCommand::Host { args }) => {
+            // stdout carries the messages of the browser
+            config.logging.terminal = TerminalLogging::Never;
+            if let Err(e) = init_log(&config.logging) {
+                eprintln!("{e}");
+            }
+            runner::redirect_child_output();
+            commands::native_host(&config, &args)
+        },
+        Command::Native(NativeCommand::Manifest { extensions, browsers, print }) => {
+            commands::native_manifest(&config, config_path, &extensions, &browsers, print)
+        },
+        #[cfg(unix)]
+        Command::Daemon(DaemonCommand::Run) => {
+            if let Err(e) = init_log(&config.logging) {
+                eprintln!("{e}");
+            }
+            commands::daemon_run(&config)
+        },
+        #[cfg(unix)]
+        Command::Daemon(DaemonCommand::Status) => commands::daemon_control(&config, &Request::Status),
+        #[cfg(unix)]
+        Command::Daemon(DaemonCommand::Stop) => commands::daemon_control(&config, &Request::Stop),
+        #[cfg(not(unix))]
+        Command::Daemon(_) => commands::daemon_unsupported(),
+        Command::Config(ConfigCommand::Schema) | Command::Man { .. } | Command::Completions { .. } => {
+            unreachable!("handled before loading the config")
+        },
+    }
+}
+
+/// Loads the configuration from the given file, or from the default file.
+///
+/// Returns the configuration and the path of the file it belongs to.  If the
+/// file does not exist the default configuration is returned.
+fn load_config(config_file : Option<&String>) -> Result<(Config, PathBuf), ProtoHandlerError> {
+    let mut config = Config::new();
+
+    if let Some(config_file) = config_file {
+        let path = Path::new(config_file).resolve().into_owned();
+        if path.exists() {
+            config.load(config_file.clone())?;
+        } else {
+            eprintln!("{config_file:?} given as config file but does not exist");
+        }
+        Ok((config, path))
+    } else {
+        let default_config = config.get_file();
+        if default_config.exists() {
+            config.load(default_config.display().to_string())?;
+        }
+        Ok((config, default_config))
     }
 }
 
-fn init_log(config : &Config) {
-    // TODO: Add the fields in the simplelog Config to our config and provide an
-    // 'into()'
-    let log_config = simplelog::Config::default();
-    let level = config.logging.convert_level();
-    let log_path = config.logging.path.resolve();
-    CombinedLogger::init(vec![
-        TermLogger::new(
-            level,
-            log_config.clone(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        ),
-        WriteLogger::new(level, log_config.clone(), File::create(log_path).unwrap()),
-    ])
-    .unwrap();
+#[cfg(test)]
+mod tests {
+    mod cli;
 }
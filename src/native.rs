@@ -0,0 +1,285 @@
+//! The `WebExtension` native-messaging host.
+//!
+//! A browser extension can send URIs to protoHandler directly, instead of
+//! opening a custom scheme the browser may block.  The browser starts the host
+//! and exchanges messages with it on stdin and stdout, each one a JSON value
+//! prefixed with its length as a 32-bit integer in native byte order.
+//!
+//! A message is a URI, an object with a `uri`, or the components of a URI:
+//!
+//! ```json
+//! "snip-proto://capture?title=Get%20started"
+//! {"uri": "snip-proto://capture?title=Get%20started"}
+//! {"scheme": "snip-proto", "subcommand": "capture", "query": {"title": "Get started"}}
+//! ```
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::error::ProtoHandlerError;
+use crate::uri::{ParsedUri, QueryParam};
+
+/// The name of the host, as given to `runtime.connectNative`
+pub const HOST_NAME: &str = "protohandlers";
+
+/// The largest message accepted from the browser
+const MAX_MESSAGE : u32 = 64 * 1024 * 1024;
+
+/// A message sent by the extension
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+#[serde(untagged)]
+pub enum Message {
+    /// A URI
+    Uri(String),
+    /// An object with a URI
+    Request {
+        /// The URI to handle
+        uri : String,
+    },
+    /// The components of a URI
+    Payload(Payload),
+}
+
+/// The components of a URI sent by the extension
+#[derive(Debug, Clone, Deserialize, PartialEq)]
+pub struct Payload {
+    /// The protocol scheme, e.g. `snip-proto`
+    pub scheme : String,
+    /// The first component after `://`, e.g. `capture`
+    #[serde(default)]
+    pub subcommand : String,
+    /// Anything after the subcommand
+    #[serde(default)]
+    pub path : String,
+    /// The payload parameters
+    #[serde(default)]
+    pub query : BTreeMap<String, String>,
+    /// The fragment
+    #[serde(default)]
+    pub fragment : Option<String>,
+}
+
+impl Message {
+    /// Returns the URI of the message, encoding the components of a payload
+    #[must_use] pub fn into_uri(self) -> String {
+        match self {
+            Self::Uri(uri) | Self::Request { uri } => uri,
+            Self::Payload(payload) => ParsedUri {
+                scheme : payload.scheme,
+                subcommand : payload.subcommand,
+                path : payload.path,
+                query : payload.query.into_iter().map(|(name, value)| QueryParam { name, value }).collect(),
+                fragment : payload.fragment,
+            }
+            .to_string(),
+        }
+    }
+}
+
+/// Returns the error of a message that could not be exchanged
+fn message_error(e : impl fmt::Display) -> ProtoHandlerError {
+    ProtoHandlerError::NativeMessageError { reason : e.to_string() }
+}
+
+/// Reads the content of a message from the browser, returning `None` when
+/// the browser closed the connection
+fn read_frame<R : Read>(input : &mut R) -> Result<Option<Vec<u8>>, ProtoHandlerError> {
+    let mut length = [0u8; 4];
+    match input.read_exact(&mut length) {
+        Ok(()) => {},
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(message_error(e)),
+    }
+    let length = u32::from_ne_bytes(length);
+    if length > MAX_MESSAGE {
+        return Err(message_error(format!("message of {length} bytes is too large")));
+    }
+    let mut content = vec![0u8; length as usize];
+    input.read_exact(&mut content).map_err(message_error)?;
+    Ok(Some(content))
+}
+
+/// Reads a message from the browser, returning `None` when the browser closed
+/// the connection.
+///
+/// # Errors
+///
+/// This function will return an error if the message cannot be read, or is
+/// not a valid message.
+pub fn read_message<R : Read>(input : &mut R) -> Result<Option<Message>, ProtoHandlerError> {
+    match read_frame(input)? {
+        Some(content) => serde_json::from_slice(&content).map(Some).map_err(message_error),
+        None => Ok(None),
+    }
+}
+
+/// Writes a message to the browser.
+///
+/// # Errors
+///
+/// This function will return an error if the message cannot be written.
+pub fn write_message<W : Write, T : Serialize>(output : &mut W, message : &T) -> Result<(), ProtoHandlerError> {
+    let content = serde_json::to_vec(message).map_err(message_error)?;
+    let length = u32::try_from(content.len()).map_err(message_error)?;
+    output.write_all(&length.to_ne_bytes()).map_err(message_error)?;
+    output.write_all(&content).map_err(message_error)?;
+    output.flush().map_err(message_error)
+}
+
+/// Handles the messages of the browser until it closes the connection,
+/// replying to each one.
+///
+/// # Arguments
+///
+/// * `input` - The messages of the browser, usually stdin.
+/// * `output` - The replies to the browser, usually stdout.
+/// * `handle` - Handles the URI of a message, returning the reply.
+///
+/// A message that is not valid is answered with `{"success": false, "error":
+/// <reason>}`.
+///
+/// # Errors
+///
+/// This function will return an error if the connection with the browser
+/// fails.
+pub fn serve<R, W, T, F>(input : &mut R, output : &mut W, mut handle : F) -> Result<(), ProtoHandlerError>
+where
+    R : Read,
+    W : Write,
+    T : Serialize,
+    F : FnMut(String) -> T,
+{
+    while let Some(content) = read_frame(input)? {
+        match serde_json::from_slice::<Message>(&content) {
+            Ok(message) => write_message(output, &handle(message.into_uri()))?,
+            Err(e) => write_message(output, &json!({ "success" : false, "error" : message_error(e).to_string() }))?,
+        }
+    }
+    Ok(())
+}
+
+/// A browser the host can be installed for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Browser {
+    /// Google Chrome
+    Chrome,
+    /// Chromium
+    Chromium,
+    /// Mozilla Firefox
+    Firefox,
+}
+
+impl Browser {
+    /// Returns the directory of the native-messaging host manifests of the user
+    ///
+    /// # Panics
+    ///
+    /// Panics if the user's home directory cannot be determined.
+    #[must_use] pub fn manifest_dir(self) -> PathBuf {
+        let home = etcetera::home_dir().expect("Unable to find home directory");
+        if cfg!(target_os = "macos") {
+            let support = home.join("Library").join("Application Support");
+            match self {
+                Self::Chrome => support.join("Google").join("Chrome"),
+                Self::Chromium => support.join("Chromium"),
+                Self::Firefox => support.join("Mozilla"),
+            }
+            .join("NativeMessagingHosts")
+        } else {
+            let config = std::env::var_os("XDG_CONFIG_HOME")
+                .map(PathBuf::from)
+                .filter(|dir| dir.is_absolute())
+                .unwrap_or_else(|| home.join(".config"));
+            match self {
+                Self::Chrome => config.join("google-chrome").join("NativeMessagingHosts"),
+                Self::Chromium => config.join("chromium").join("NativeMessagingHosts"),
+                Self::Firefox => home.join(".mozilla").join("native-messaging-hosts"),
+            }
+        }
+    }
+
+    /// Returns the manifest of the host for this browser.
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The absolute path of the program the browser starts.
+    /// * `extensions` - The ids of the extensions allowed to use the host.
+    #[must_use] pub fn manifest(self, path : &Path, extensions : &[String]) -> serde_json::Value {
+        let mut manifest = json!({
+            "name" : HOST_NAME,
+            "description" : "protoHandler, handles URIs sent by the browser",
+            "path" : path.display().to_string(),
+            "type" : "stdio",
+        });
+        match self {
+            Self::Chrome | Self::Chromium => {
+                let origins : Vec<String> = extensions
+                    .iter()
+                    .map(|id| format!("chrome-extension://{}/", id.trim_start_matches("chrome-extension://").trim_end_matches('/')))
+                    .collect();
+                manifest["allowed_origins"] = json!(origins);
+            },
+            Self::Firefox => manifest["allowed_extensions"] = json!(extensions),
+        }
+        manifest
+    }
+
+    /// Writes the manifest of the host for this browser, returning its path.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the manifest cannot be written.
+    pub fn install(self, path : &Path, extensions : &[String]) -> Result<PathBuf, ProtoHandlerError> {
+        let dir = self.manifest_dir();
+        let file = dir.join(format!("{HOST_NAME}.json"));
+        let err = |e : io::Error| ProtoHandlerError::ManifestError {
+            path : file.display().to_string(),
+            reason : e.to_string(),
+        };
+        let content = serde_json::to_string_pretty(&self.manifest(path, extensions)).map_err(|e| err(io::Error::other(e)))?;
+        std::fs::create_dir_all(&dir).map_err(err)?;
+        std::fs::write(&file, content + "\n").map_err(err)?;
+        Ok(file)
+    }
+}
+
+impl fmt::Display for Browser {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Chrome => "chrome",
+            Self::Chromium => "chromium",
+            Self::Firefox => "firefox",
+        };
+        f.write_str(name)
+    }
+}
+
+/// Writes the script the browsers start, which runs `program native host`
+/// with the configuration file `config`, and returns its path.
+///
+/// The manifests cannot give arguments to the program, hence the script.
+///
+/// # Errors
+///
+/// This function will return an error if the script cannot be written.
+#[cfg(unix)]
+pub fn install_launcher(dir : &Path, program : &Path, config : &Path) -> Result<PathBuf, ProtoHandlerError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let file = dir.join("native-host");
+    let err = |e : io::Error| ProtoHandlerError::ManifestError {
+        path : file.display().to_string(),
+        reason : e.to_string(),
+    };
+    let quote = |path : &Path| format!("'{}'", path.display().to_string().replace('\'', "'\\''"));
+    let script = format!("#!/bin/sh\nexec {} -c {} native host \"$@\"\n", quote(program), quote(config));
+    std::fs::create_dir_all(dir).map_err(err)?;
+    std::fs::write(&file, script).map_err(err)?;
+    std::fs::set_permissions(&file, std::fs::Permissions::from_mode(0o755)).map_err(err)?;
+    Ok(file)
+}
@@ -0,0 +1,148 @@
+//! Desktop notifications of the outcome of a URI.
+//!
+//! protoHandler usually runs without a terminal, so a protocol can show a
+//! notification when its URIs are handled, or fail to be.  Notifications are
+//! sent with the freedesktop notification D-Bus interface, or with a
+//! `notify-send` style program.
+
+use std::process::Command;
+
+use simplelog::{debug, warn};
+
+use crate::config::{Config, NotificationBackend, NotifyConfig, NotifyOn};
+use crate::error::ProtoHandlerError;
+use crate::redact::redacted;
+use crate::runner::{child_stdout, lookup_protocol, Invocation};
+use crate::template::{render, Context};
+use crate::uri::ParsedUri;
+
+/// A notification about the outcome of a URI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Notification {
+    /// The title of the notification
+    pub title : String,
+    /// The body of the notification
+    pub body : String,
+    /// True if the URI could not be handled
+    pub critical : bool,
+}
+
+impl Notification {
+    /// Returns the urgency of the notification, as named by `notify-send`
+    #[must_use] pub fn urgency(&self) -> &'static str {
+        if self.critical { "critical" } else { "normal" }
+    }
+}
+
+/// Returns the notification of the invocation, if the protocol shows one for
+/// its outcome.
+///
+/// The URI and the error are redacted as configured in `redaction`.
+#[must_use] pub fn notification(invocation : &Invocation, notify : &NotifyConfig, config : &Config) -> Option<Notification> {
+    let success = invocation.success();
+    match (notify.on, success) {
+        (NotifyOn::Always, _) | (NotifyOn::Success, true) | (NotifyOn::Error, false) => {},
+        _ => return None,
+    }
+
+    let uri = redacted(&invocation.uri);
+    let parser = invocation.protocol.as_ref().and_then(|p| lookup_protocol(p, config)).map(|p| p.parser).unwrap_or_default();
+    let parsed = ParsedUri::parse_as(&uri, parser).unwrap_or_default();
+    let context = Context::new(&uri, &parsed)
+        .with("protocol", invocation.protocol.clone().unwrap_or_default())
+        .with("route", invocation.route.clone().unwrap_or_default())
+        .with("status", if success { "succeeded" } else { "failed" })
+        .with("error", invocation.error().map(|e| redacted(&e)).unwrap_or_default())
+        .with("exit_code", invocation.exit_code().map(|c| c.to_string()).unwrap_or_default());
+    let title = notify.title.as_deref().unwrap_or(&config.notifications.title);
+    let body = notify.body.as_deref().unwrap_or(&config.notifications.body);
+    Some(Notification {
+        title : render(title, &context).trim().to_string(),
+        body : render(body, &context).trim().to_string(),
+        critical : !success,
+    })
+}
+
+/// Shows the notification with the configured backend.
+///
+/// # Errors
+///
+/// This function will return an error if the notification could not be shown.
+pub fn send(notification : &Notification, config : &Config) -> Result<(), ProtoHandlerError> {
+    match config.notifications.backend {
+        NotificationBackend::Command => send_command(notification, config),
+        NotificationBackend::Dbus => send_dbus(notification),
+        NotificationBackend::Auto => send_dbus(notification).or_else(|e| {
+            debug!("{e}, using {}", config.notifications.program);
+            send_command(notification, config)
+        }),
+    }
+}
+
+/// Shows the notification of the invocation, if the protocol shows one,
+/// logging the failures
+pub fn notify(invocation : &Invocation, notify : &NotifyConfig, config : &Config) {
+    if let Some(notification) = notification(invocation, notify, config) {
+        if let Err(e) = send(&notification, config) {
+            warn!("{e}");
+        }
+    }
+}
+
+/// Shows the notification by running the configured program
+fn send_command(notification : &Notification, config : &Config) -> Result<(), ProtoHandlerError> {
+    let notifications = &config.notifications;
+    let parsed = ParsedUri::default();
+    let context = Context::new("", &parsed)
+        .with("title", notification.title.clone())
+        .with("body", notification.body.clone())
+        .with("urgency", notification.urgency());
+    let status = Command::new(&notifications.program)
+        .args(notifications.args.iter().map(|a| render(a, &context)))
+        .stdout(child_stdout())
+        .status()
+        .map_err(|e| ProtoHandlerError::NotifyError { reason : format!("'{}': {e}", notifications.program) })?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(ProtoHandlerError::NotifyError { reason : format!("'{}' {status}", notifications.program) })
+    }
+}
+
+/// Shows the notification with the freedesktop notification D-Bus interface
+#[cfg(target_os = "linux")]
+fn send_dbus(notification : &Notification) -> Result<(), ProtoHandlerError> {
+    use std::collections::HashMap;
+
+    use zbus::zvariant::Value;
+
+    let err = |e : zbus::Error| ProtoHandlerError::NotifyError { reason : e.to_string() };
+    let connection = zbus::blocking::Connection::session().map_err(err)?;
+    let urgency : u8 = if notification.critical { 2 } else { 1 };
+    let hints : HashMap<&str, Value> = HashMap::from([("urgency", Value::from(urgency))]);
+    connection
+        .call_method(
+            Some("org.freedesktop.Notifications"),
+            "/org/freedesktop/Notifications",
+            Some("org.freedesktop.Notifications"),
+            "Notify",
+            &(
+                "protoHandler",
+                0u32,
+                "",
+                notification.title.as_str(),
+                notification.body.as_str(),
+                Vec::<&str>::new(),
+                hints,
+                -1i32,
+            ),
+        )
+        .map_err(err)?;
+    Ok(())
+}
+
+/// Shows the notification with the freedesktop notification D-Bus interface
+#[cfg(not(target_os = "linux"))]
+fn send_dbus(_notification : &Notification) -> Result<(), ProtoHandlerError> {
+    Err(ProtoHandlerError::NotifyError { reason : String::from("D-Bus is only supported on Linux") })
+}
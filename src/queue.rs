@@ -0,0 +1,146 @@
+//! The dead-letter queue, where the URIs that could not be handled are kept.
+//!
+//! When every attempt of a protocol with `retry.dead_letter` fails, the URI is
+//! stored in the `dead-letter` directory of the state directory, one JSON file
+//! per URI, so that it can be retried once the problem is fixed.  Like the
+//! history, the queue holds the original URI and is readable by the user only.
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::config::Config;
+use crate::error::ProtoHandlerError;
+use crate::runner::Invocation;
+
+const QUEUE_DIR: &str = "dead-letter";
+
+/// A URI in the dead-letter queue
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DeadLetter {
+    /// The id of the invocation that failed
+    pub id : String,
+    /// When the URI was queued, in RFC 3339 format
+    pub queued : String,
+    /// The URI as it was given
+    pub uri : String,
+    /// The protocol found in the URI
+    pub protocol : Option<String>,
+    /// How many times the handler was run
+    pub attempts : u32,
+    /// The exit code of the last attempt, if it ran to completion
+    pub exit_code : Option<i32>,
+    /// The error that stopped the last attempt
+    pub error : Option<String>,
+}
+
+impl DeadLetter {
+    /// Creates the dead letter of an invocation
+    #[must_use] pub fn new(invocation : &Invocation) -> Self {
+        Self {
+            id : invocation.id.clone(),
+            queued : OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default(),
+            uri : invocation.uri.clone(),
+            protocol : invocation.protocol.clone(),
+            attempts : invocation.attempts,
+            exit_code : invocation.exit_code(),
+            error : invocation.error(),
+        }
+    }
+}
+
+impl fmt::Display for DeadLetter {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}  {}  [{} attempts]  {}", &self.id[..8.min(self.id.len())], self.queued, self.attempts, self.uri)
+    }
+}
+
+/// Returns the directory of the dead-letter queue
+#[must_use] pub fn queue_dir(config : &Config) -> PathBuf {
+    config.get_state_directory().join(QUEUE_DIR)
+}
+
+/// Returns the error for a failed access to the queue
+fn queue_error(path : &Path, e : &io::Error) -> ProtoHandlerError {
+    ProtoHandlerError::QueueError { path : path.display().to_string(), reason : e.to_string() }
+}
+
+/// Adds the URI of the invocation to the dead-letter queue.
+///
+/// # Errors
+///
+/// This function will return an error if the queue cannot be written.
+pub fn push(invocation : &Invocation, config : &Config) -> Result<DeadLetter, ProtoHandlerError> {
+    let letter = DeadLetter::new(invocation);
+    let dir = queue_dir(config);
+    let path = dir.join(format!("{}.json", letter.id));
+    let content = serde_json::to_string_pretty(&letter).map_err(|e| queue_error(&path, &io::Error::other(e)))?;
+
+    std::fs::create_dir_all(&dir).map_err(|e| queue_error(&dir, &e))?;
+    let mut options = OpenOptions::new();
+    options.create_new(true).write(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&path)
+        .and_then(|mut f| f.write_all(content.as_bytes()))
+        .map_err(|e| queue_error(&path, &e))?;
+    Ok(letter)
+}
+
+/// Loads the URIs of the dead-letter queue, oldest first.
+///
+/// Files that cannot be parsed are skipped.
+///
+/// # Errors
+///
+/// This function will return an error if the queue exists but cannot be read.
+pub fn load(config : &Config) -> Result<Vec<DeadLetter>, ProtoHandlerError> {
+    let dir = queue_dir(config);
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(queue_error(&dir, &e)),
+    };
+    let mut letters : Vec<DeadLetter> = entries
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+        .filter_map(|entry| std::fs::read_to_string(entry.path()).ok())
+        .filter_map(|content| serde_json::from_str(&content).ok())
+        .collect();
+    letters.sort_by(|a, b| a.queued.cmp(&b.queued));
+    Ok(letters)
+}
+
+/// Finds the queued URI whose id starts with the given prefix.
+///
+/// # Errors
+///
+/// This function will return an error if no queued URI, or more than one,
+/// matches the prefix.
+pub fn find<'a>(letters : &'a [DeadLetter], id : &str) -> Result<&'a DeadLetter, ProtoHandlerError> {
+    let mut matches = letters.iter().filter(|l| l.id.starts_with(id));
+    match (matches.next(), matches.next()) {
+        (Some(letter), None) => Ok(letter),
+        (None, _) => Err(ProtoHandlerError::QueueEntryNotFound { id : id.to_string() }),
+        (Some(_), Some(_)) => Err(ProtoHandlerError::QueueEntryAmbiguous { id : id.to_string() }),
+    }
+}
+
+/// Removes a URI from the dead-letter queue.
+///
+/// # Errors
+///
+/// This function will return an error if the URI cannot be removed.
+pub fn remove(letter : &DeadLetter, config : &Config) -> Result<(), ProtoHandlerError> {
+    let path = queue_dir(config).join(format!("{}.json", letter.id));
+    std::fs::remove_file(&path).map_err(|e| queue_error(&path, &e))
+}
@@ -0,0 +1,79 @@
+//! Redaction of sensitive information found in URIs.
+//!
+//! Some links carry tokens or passwords in their payload or user information.
+//! Before a URI, or a commandline containing it, is written to a log the
+//! sensitive parts are replaced.
+//!
+//! Log messages are written from places that do not have the configuration at
+//! hand, so the redaction configuration used by `redacted` is set once for the
+//! whole program with `set_config`.
+
+use std::sync::OnceLock;
+
+use regex::Regex;
+
+use crate::config::RedactionConfig;
+
+/// The text replacing redacted values
+pub const REDACTED : &str = "REDACTED";
+
+/// The redaction configuration used for log messages
+static CONFIG : OnceLock<RedactionConfig> = OnceLock::new();
+
+/// Sets the redaction configuration used by `redacted`.
+///
+/// Only the first call has an effect.  Until it is called, the default
+/// redaction configuration is used.
+pub fn set_config(config : &RedactionConfig) {
+    let _ = CONFIG.set(config.clone());
+}
+
+/// Redacts sensitive parts of a text to be logged, using the configuration
+/// given to `set_config`.
+#[must_use] pub fn redacted(text : &str) -> String {
+    match CONFIG.get() {
+        Some(config) => redact(text, config),
+        None => redact(text, &RedactionConfig::default()),
+    }
+}
+
+/// Redacts sensitive parts of a URI, or any text containing one.
+///
+/// # Arguments
+///
+/// * `text` - The URI, or a text containing it.
+/// * `config` - A reference to the redaction configuration.
+///
+/// # Returns
+///
+/// * `String` - The text, with the values of the redacted query parameters,
+///   the user information and the matches of the patterns replaced.
+///
+/// # Panics
+///
+/// Panics if the redaction regular expressions built from the query keys are
+/// invalid.  Invalid patterns are ignored.
+#[must_use] pub fn redact(text : &str, config : &RedactionConfig) -> String {
+    if !config.enabled {
+        return text.to_string();
+    }
+    let mut text = text.to_string();
+
+    if config.userinfo {
+        let re = Regex::new(r#"(?<scheme>[a-zA-Z][a-zA-Z0-9+.\-]*://)[^/?#@\s"]+@"#).unwrap();
+        text = re.replace_all(&text, format!("${{scheme}}{REDACTED}@")).into_owned();
+    }
+
+    if !config.query_keys.is_empty() {
+        let keys : Vec<String> = config.query_keys.iter().map(|k| regex::escape(k)).collect();
+        let re = Regex::new(&format!(r#"(?i)([?&](?:{})=)[^&#"\s]*"#, keys.join("|"))).unwrap();
+        text = re.replace_all(&text, format!("${{1}}{REDACTED}")).into_owned();
+    }
+
+    for pattern in &config.patterns {
+        if let Ok(re) = Regex::new(pattern) {
+            text = re.replace_all(&text, REDACTED).into_owned();
+        }
+    }
+    text
+}
@@ -0,0 +1,101 @@
+//! Rewriting of URIs before they are handled.
+//!
+//! Each protocol can have rules rewriting its URIs, so that legacy links keep
+//! working when schemes or parameters are renamed.  The rules are applied in
+//! order, after the URI is parsed and before it is routed to a handler.
+
+use regex::Regex;
+use simplelog::debug;
+
+use crate::config::{RewriteRule, UriParser};
+use crate::error::ProtoHandlerError;
+use crate::redact::redacted;
+use crate::uri::{ParsedUri, QueryParam};
+
+/// Applies the rules to the URI.
+///
+/// # Arguments
+///
+/// * `uri` - A string slice representing the URI.
+/// * `rules` - The rewrite rules of the protocol.
+/// * `parser` - The parser of the protocol, used by the rules changing
+///   components of the URI.
+///
+/// # Returns
+///
+/// * `Ok(String)` - The rewritten URI, or the URI itself if no rule applied.
+/// * `Err(ProtoHandlerError)` - If a rule is invalid.
+///
+/// # Errors
+///
+/// This function will return an error if a `match` regular expression is
+/// invalid, or if a rule produced a URI that cannot be parsed.
+///
+/// # Examples
+///
+/// ```
+/// use std::collections::BTreeMap;
+/// use protohandlers::config::{RewriteRule, UriParser};
+/// use protohandlers::rewrite::apply;
+///
+/// let rules = vec![RewriteRule {
+///     scheme : Some(String::from("snip")),
+///     rename : BTreeMap::from([(String::from("t"), String::from("title"))]),
+///     ..RewriteRule::default()
+/// }];
+/// let uri = apply("snip-proto://capture?t=Hello", &rules, UriParser::Standard).unwrap();
+/// assert_eq!("snip://capture?title=Hello", uri);
+/// ```
+pub fn apply(uri : &str, rules : &[RewriteRule], parser : UriParser) -> Result<String, ProtoHandlerError> {
+    let mut uri = uri.to_string();
+    for (i, rule) in rules.iter().enumerate() {
+        let before = uri.clone();
+        if let Some(pattern) = &rule.matches {
+            let re = Regex::new(pattern).map_err(|e| ProtoHandlerError::RewriteError {
+                pattern : pattern.clone(),
+                reason : e.to_string(),
+            })?;
+            if !re.is_match(&uri) {
+                debug!("Rewrite rule {i} does not match {}", redacted(&uri));
+                continue;
+            }
+            if let Some(replace) = &rule.replace {
+                uri = re.replace(&uri, replace.as_str()).into_owned();
+            }
+        }
+        if has_component_changes(rule) {
+            uri = change_components(&uri, rule, parser)?.to_string();
+        }
+        if uri != before {
+            debug!("Rewrite rule {i} rewrote {} to {}", redacted(&before), redacted(&uri));
+        }
+    }
+    Ok(uri)
+}
+
+/// Returns true if the rule changes components of the URI
+fn has_component_changes(rule : &RewriteRule) -> bool {
+    rule.scheme.is_some() || rule.subcommand.is_some() || !rule.rename.is_empty() || !rule.defaults.is_empty()
+}
+
+/// Changes the components of the URI as configured in the rule
+fn change_components(uri : &str, rule : &RewriteRule, parser : UriParser) -> Result<ParsedUri, ProtoHandlerError> {
+    let mut parsed = ParsedUri::parse_as(uri, parser)?;
+    if let Some(scheme) = &rule.scheme {
+        parsed.scheme.clone_from(scheme);
+    }
+    if let Some(subcommand) = &rule.subcommand {
+        parsed.subcommand.clone_from(subcommand);
+    }
+    for param in &mut parsed.query {
+        if let Some(name) = rule.rename.get(&param.name) {
+            param.name.clone_from(name);
+        }
+    }
+    for (name, value) in &rule.defaults {
+        if parsed.param(name).is_none() {
+            parsed.query.push(QueryParam { name : name.clone(), value : value.clone() });
+        }
+    }
+    Ok(parsed)
+}
@@ -0,0 +1,398 @@
+//! Matching of URIs to the routes of a protocol.
+//!
+//! A route handles the URIs with its `subcommand`, or whose path matches its
+//! `path` pattern.  A pattern is made of segments separated by `/`, starting
+//! with the subcommand:
+//!
+//! ```text
+//! /open/{notebook}/{*path}
+//! ```
+//!
+//! * `open` - the segment must be `open`
+//! * `{name}` - captures a segment as `name`
+//! * `{name:regex}` - captures a segment matching the regular expression
+//! * `{name?}`, `{name?:regex}` - captures a segment, if there is one
+//! * `{*name}` - captures the rest of the path, which can be empty; it must be
+//!   the last segment
+//!
+//! The captured values are available to the templates of the route.  When
+//! several routes match a URI, the most specific one handles it: the one with
+//! the most literal segments, then the most constrained captures, then the
+//! most captures.  The subcommand of a route without a pattern counts as a
+//! literal segment, so `/{a}/{b}` is less specific than `subcommand: open`.
+//! Between routes as specific otherwise, a pattern without `{*name}` is more
+//! specific than one with it, and a pattern than a `subcommand` alone.  The
+//! `*` route handles the URIs no other route matches.
+//!
+//! The patterns and regular expressions of the routes are compiled when the
+//! configuration is loaded, see `Config::compile_routes`.
+//!
+//! A route can also require a condition on the query parameters of the URI,
+//! its `when`:
+//!
+//! ```yaml
+//! when:
+//!   or:
+//!     - { param: template, equals: c }
+//!     - and:
+//!         - { param: url, regex: "github\\.com" }
+//!         - not: { exists: draft }
+//! ```
+//!
+//! Between routes that are as specific otherwise, the one with the most
+//! conditions handles the URI.
+
+use std::collections::BTreeMap;
+
+use regex::Regex;
+use serde::Serialize;
+use simplelog::warn;
+
+use crate::config::{Predicate, RouteConfig};
+use crate::error::ProtoHandlerError;
+use crate::uri::ParsedUri;
+
+/// A value captured by the path pattern of a route
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Capture {
+    /// The name of the capture in the pattern
+    pub name : String,
+    /// The decoded value of the capture
+    pub value : String,
+}
+
+/// How specific a route is, compared field by field
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Specificity {
+    /// The number of literal segments, the subcommand of a route without a
+    /// pattern included
+    pub literals : usize,
+    /// The number of captures with a regular expression
+    pub constrained : usize,
+    /// The number of captures that are not optional
+    pub captures : usize,
+    /// True if the route does not match every path below its segments
+    pub bounded : bool,
+    /// True if the route has a path pattern
+    pub pattern : bool,
+    /// The number of conditions on the query parameters
+    pub conditions : usize,
+}
+
+/// A compiled path pattern
+#[derive(Debug, Clone)]
+pub struct PathPattern {
+    regex : Regex,
+    names : Vec<String>,
+    /// How specific the pattern is
+    pub specificity : Specificity,
+}
+
+impl PathPattern {
+    /// Compiles a path pattern.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if a capture is not valid, or if
+    /// `{*name}` is not the last segment.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use protohandlers::routing::PathPattern;
+    ///
+    /// let pattern = PathPattern::parse("/open/{notebook}/{*path}").unwrap();
+    /// let captures = pattern.captures("/open/projects/foo/readme.md").unwrap();
+    /// assert_eq!("projects", captures[0].value);
+    /// assert_eq!("foo/readme.md", captures[1].value);
+    /// assert!(pattern.captures("/close/projects").is_none());
+    /// ```
+    pub fn parse(pattern : &str) -> Result<Self, ProtoHandlerError> {
+        let err = |reason : String| ProtoHandlerError::RouteError { pattern : pattern.to_string(), reason };
+        let segments : Vec<&str> = pattern.trim_start_matches('/').split('/').collect();
+        let mut re = String::from("^");
+        let mut names = Vec::new();
+        let mut specificity = Specificity { bounded : true, pattern : true, ..Specificity::default() };
+        for (i, segment) in segments.iter().enumerate() {
+            let Some(capture) = segment.strip_prefix('{').and_then(|s| s.strip_suffix('}')) else {
+                if segment.contains(['{', '}']) {
+                    return Err(err(format!("'{segment}' must be a literal or a single capture")));
+                }
+                re.push('/');
+                re.push_str(&regex::escape(segment));
+                specificity.literals += 1;
+                continue;
+            };
+            let (name, constraint) = match capture.split_once(':') {
+                Some((name, constraint)) => (name, Some(constraint)),
+                None => (capture, None),
+            };
+            let (name, rest) = match name.strip_prefix('*') {
+                Some(name) if i + 1 == segments.len() && constraint.is_none() => (name, true),
+                Some(_) => return Err(err(format!("'{segment}' must be the last segment, without a constraint"))),
+                None => (name, false),
+            };
+            let (name, optional) = name.strip_suffix('?').map_or((name, false), |name| (name, true));
+            if !is_name(name) || names.iter().any(|n| n == name) {
+                return Err(err(format!("'{name}' is not a valid capture name, or is used twice")));
+            }
+            if let Some(constraint) = constraint {
+                Regex::new(constraint).map_err(|e| err(e.to_string()))?;
+            }
+            let value = constraint.unwrap_or("[^/]+");
+            let group = if rest {
+                specificity.bounded = false;
+                format!("(?:/(?<{name}>.*))?")
+            } else if optional {
+                format!("(?:/(?<{name}>{value}))?")
+            } else {
+                specificity.captures += 1;
+                format!("/(?<{name}>{value})")
+            };
+            re.push_str(&group);
+            if constraint.is_some() {
+                specificity.constrained += 1;
+            }
+            names.push(name.to_string());
+        }
+        re.push_str("/?$");
+        let regex = Regex::new(&re).map_err(|e| err(e.to_string()))?;
+        Ok(Self { regex, names, specificity })
+    }
+
+    /// Returns the values captured in the path, if the pattern matches it
+    #[must_use] pub fn captures(&self, path : &str) -> Option<Vec<Capture>> {
+        let c = self.regex.captures(path)?;
+        let captures = self
+            .names
+            .iter()
+            .map(|name| Capture {
+                name : name.clone(),
+                value : c.name(name).map(|m| m.as_str().to_string()).unwrap_or_default(),
+            })
+            .collect();
+        Some(captures)
+    }
+}
+
+/// Returns true if the text can name a capture
+fn is_name(name : &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// The path pattern and the regular expressions of the condition of a route,
+/// compiled once
+#[derive(Debug, Clone, Default)]
+pub struct CompiledRoute {
+    /// The path pattern, if the route has one
+    pattern : Option<PathPattern>,
+    /// True if the path pattern is not valid, the route then matches nothing
+    invalid : bool,
+    /// The valid regular expressions of the condition, by their text
+    regexes : BTreeMap<String, Regex>,
+}
+
+impl CompiledRoute {
+    /// Compiles the path pattern and the regular expressions of the route,
+    /// warning about those that are not valid
+    #[must_use] pub fn new(route : &RouteConfig) -> Self {
+        let mut compiled = Self::default();
+        if let Some(pattern) = &route.path {
+            match PathPattern::parse(pattern) {
+                Ok(pattern) => compiled.pattern = Some(pattern),
+                Err(e) => {
+                    warn!("{e}");
+                    compiled.invalid = true;
+                },
+            }
+        }
+        if let Some(when) = &route.when {
+            compile_regexes(when, &mut compiled.regexes);
+        }
+        compiled
+    }
+}
+
+/// The compiled route is derived from the other fields of the route, so it
+/// does not make routes different
+impl PartialEq for CompiledRoute {
+    fn eq(&self, _ : &Self) -> bool {
+        true
+    }
+}
+
+/// Compiles the regular expressions of the predicate
+fn compile_regexes(predicate : &Predicate, regexes : &mut BTreeMap<String, Regex>) {
+    match predicate {
+        Predicate::Regex { regex, .. } => match Regex::new(regex) {
+            Ok(re) => {
+                regexes.insert(regex.clone(), re);
+            },
+            Err(e) => warn!("Invalid regular expression '{regex}': {e}"),
+        },
+        Predicate::Equals { .. } | Predicate::Exists { .. } => {},
+        Predicate::Not { not } => compile_regexes(not, regexes),
+        Predicate::And { and : predicates } | Predicate::Or { or : predicates } => {
+            for p in predicates {
+                compile_regexes(p, regexes);
+            }
+        },
+    }
+}
+
+/// Calls `f` with the compiled route, compiling it if the route was not
+/// compiled when the configuration was loaded
+fn with_compiled<T>(route : &RouteConfig, f : impl FnOnce(&CompiledRoute) -> T) -> T {
+    match &route.compiled {
+        Some(compiled) => f(compiled),
+        None => f(&CompiledRoute::new(route)),
+    }
+}
+
+/// A route handling a URI
+#[derive(Debug, Clone)]
+pub struct RouteMatch<'a> {
+    /// The route
+    pub route : &'a RouteConfig,
+    /// The values captured by the path pattern of the route
+    pub captures : Vec<Capture>,
+    /// How specific the route is
+    pub specificity : Specificity,
+}
+
+/// Matches the URI with a route, returning how specific the route is and the
+/// values it captured.
+///
+/// A route whose pattern is not valid matches nothing.
+#[must_use] pub fn matches(route : &RouteConfig, parsed : &ParsedUri) -> Option<(Specificity, Vec<Capture>)> {
+    let any = route.subcommand.is_empty() || route.subcommand == "*";
+    if !any && route.subcommand != parsed.subcommand {
+        return None;
+    }
+    with_compiled(route, |compiled| {
+        let conditions = match &route.when {
+            Some(when) if !evaluate(when, &compiled.regexes, parsed, 0, &mut Vec::new()) => return None,
+            Some(when) => count(when),
+            None => 0,
+        };
+        let (specificity, captures) = match &compiled.pattern {
+            _ if compiled.invalid => return None,
+            Some(pattern) => {
+                let captures = pattern.captures(&format!("/{}{}", parsed.subcommand, parsed.path))?;
+                (pattern.specificity, captures)
+            },
+            None if any => (Specificity::default(), Vec::new()),
+            None => (Specificity { literals : 1, ..Specificity::default() }, Vec::new()),
+        };
+        Some((Specificity { conditions, ..specificity }, captures))
+    })
+}
+
+/// Returns the most specific route handling the URI, the first one configured
+/// if several are as specific
+#[must_use] pub fn find<'a>(routes : &'a [RouteConfig], parsed : &ParsedUri) -> Option<RouteMatch<'a>> {
+    let mut best : Option<RouteMatch> = None;
+    for route in routes {
+        let Some((specificity, captures)) = matches(route, parsed) else {
+            continue;
+        };
+        if best.as_ref().is_none_or(|b| specificity > b.specificity) {
+            best = Some(RouteMatch { route, captures, specificity });
+        }
+    }
+    best
+}
+
+/// The outcome of a condition of a route, or of one of the conditions it
+/// combines
+#[derive(Debug, Clone, Serialize, PartialEq, Eq)]
+pub struct Condition {
+    /// The name of the route
+    pub route : String,
+    /// The condition
+    pub predicate : String,
+    /// True if the condition holds
+    pub passed : bool,
+    /// How deep the condition is in the condition of the route, 0 for the
+    /// condition itself
+    pub depth : usize,
+}
+
+/// Evaluates the condition of the route for the URI, returning the outcome
+/// of the condition and of each condition it combines
+#[must_use] pub fn conditions(route : &RouteConfig, parsed : &ParsedUri) -> Vec<Condition> {
+    let Some(when) = &route.when else {
+        return Vec::new();
+    };
+    let mut outcomes = Vec::new();
+    with_compiled(route, |compiled| evaluate(when, &compiled.regexes, parsed, 0, &mut outcomes));
+    let name = route.name();
+    outcomes
+        .into_iter()
+        .map(|(predicate, passed, depth)| Condition { route : name.clone(), predicate, passed, depth })
+        .collect()
+}
+
+/// Returns true if the predicate holds for the URI, recording the outcome of
+/// the predicate and of those it combines, in order.
+///
+/// Every predicate is evaluated, so that all of them can be explained.  A
+/// regular expression that is not valid, and so is not in `regexes`, does not
+/// match.
+fn evaluate(
+    predicate : &Predicate,
+    regexes : &BTreeMap<String, Regex>,
+    parsed : &ParsedUri,
+    depth : usize,
+    outcomes : &mut Vec<(String, bool, usize)>,
+) -> bool {
+    let i = outcomes.len();
+    outcomes.push((predicate.to_string(), false, depth));
+    let holds = match predicate {
+        Predicate::Equals { param, equals } => parsed.param(param) == Some(equals.as_str()),
+        Predicate::Regex { param, regex } => {
+            regexes.get(regex).is_some_and(|re| parsed.param(param).is_some_and(|value| re.is_match(value)))
+        },
+        Predicate::Exists { exists } => parsed.param(exists).is_some(),
+        Predicate::Not { not } => !evaluate(not, regexes, parsed, depth + 1, outcomes),
+        Predicate::And { and } => {
+            let results : Vec<bool> = and.iter().map(|p| evaluate(p, regexes, parsed, depth + 1, outcomes)).collect();
+            results.into_iter().all(|r| r)
+        },
+        Predicate::Or { or } => {
+            let results : Vec<bool> = or.iter().map(|p| evaluate(p, regexes, parsed, depth + 1, outcomes)).collect();
+            results.into_iter().any(|r| r)
+        },
+    };
+    outcomes[i].1 = holds;
+    holds
+}
+
+/// Returns the number of conditions on a parameter in the predicate
+fn count(predicate : &Predicate) -> usize {
+    match predicate {
+        Predicate::Equals { .. } | Predicate::Regex { .. } | Predicate::Exists { .. } => 1,
+        Predicate::Not { not } => count(not),
+        Predicate::And { and } => and.iter().map(count).sum(),
+        Predicate::Or { or } => or.iter().map(count).sum(),
+    }
+}
+
+/// Checks the regular expressions of the predicate.
+///
+/// # Errors
+///
+/// This function will return an error if a regular expression of the
+/// predicate is not valid.
+pub fn validate(predicate : &Predicate) -> Result<(), ProtoHandlerError> {
+    match predicate {
+        Predicate::Regex { regex, .. } => Regex::new(regex)
+            .map(|_| ())
+            .map_err(|e| ProtoHandlerError::RouteError { pattern : regex.clone(), reason : e.to_string() }),
+        Predicate::Equals { .. } | Predicate::Exists { .. } => Ok(()),
+        Predicate::Not { not } => validate(not),
+        Predicate::And { and : predicates } | Predicate::Or { or : predicates } => predicates.iter().try_for_each(validate),
+    }
+}
@@ -1,10 +1,27 @@
-use std::process::Command;
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt;
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 
 use regex::Regex;
-use simplelog::{debug, info};
+use serde::Serialize;
+use simplelog::{debug, error, info, warn};
+use time::OffsetDateTime;
+use uuid::Uuid;
 
-use crate::config::{Config, ProtocolConfig, ShellConfig};
+use crate::concurrency::{self, Admission};
+use crate::config::{ConcurrencyMode, Config, FallbackAction, HandlerKind, ProtocolConfig, ShellConfig};
+use crate::dedupe;
 use crate::error::ProtoHandlerError;
+use crate::handlers::{AppendFileHandler, ForwardHandler, HttpHandler};
+use crate::notify::{self, Notification};
+use crate::queue;
+use crate::redact::redacted;
+use crate::rewrite;
+use crate::template;
+use crate::uri::ParsedUri;
 
 /// Builds a command based on the given URI and configuration.
 ///
@@ -31,92 +48,630 @@ use crate::error::ProtoHandlerError;
 /// # Examples
 ///
 /// ```
-/// let uri = "http://example.com".to_string();
-/// let config = Config::new();
-/// let command = build_command(uri, config);
-/// assert!(command.is_ok());
+/// use protohandlers::config::{Config, ProtocolConfig, ProtocolScriptConfig, ProtocolShellConfig};
+/// use protohandlers::runner::build_command;
+///
+/// let mut config = Config::new();
+/// config.protocols.push(ProtocolConfig {
+///     name : String::from("snip-proto"),
+///     desc : String::from("A web snipping protocol"),
+///     script : ProtocolScriptConfig { name : String::from("capture.ps1"), args : vec![String::from("-Uri")] },
+///     shell : ProtocolShellConfig { name : String::from("pwsh"), args : Vec::new() },
+///     ..ProtocolConfig::default()
+/// });
+///
+/// let uri = "snip-proto://capture?template=c".to_string();
+/// let command = build_command(uri, &config).unwrap();
+/// assert_eq!("pwsh", command.get_program());
+///
+/// let command = build_command("http://example.com".to_string(), &config);
+/// assert!(command.is_err());
 /// ```
-pub fn build_command(uri : String, config : Config) -> Result<Command, ProtoHandlerError> {
+pub fn build_command(uri : String, config : &Config) -> Result<Command, ProtoHandlerError> {
     let proto : String;
-    let program : String;
-    let mut commandline : Vec<String>;
-    commandline = Vec::new();
 
-    if let Some(p) = get_protocol(&uri) {
+    if let Some(p) = get_protocol(&uri, config) {
         proto = p;
         info!("Uri contains protocol {proto}");
     } else {
         return Err(ProtoHandlerError::UriParseError { uri });
     }
 
-    if let Some(protocol_config) = lookup_protocol(&proto, &config) {
+    if let Some(protocol_config) = lookup_protocol(&proto, config) {
         debug!("Found configuration for protocol '{proto}'");
-        let shell_name = protocol_config.shell.name;
-        debug!("Shell is configured as '{shell_name}'");
-
-        if let Some(shell_config) = lookup_shell(&shell_name, &config) {
-            program = shell_config.cmd;
-            commandline.extend(shell_config.args);
-        } else {
-            return Err(ProtoHandlerError::ShellNotConfigured { sh : shell_name });
-        }
+        let (protocol_config, _) = lookup_route(protocol_config, &uri);
+        protocol_command(&uri, protocol_config, config)
+    } else {
+        info!("protocol '{proto}' not configured");
+        Err(ProtoHandlerError::ProtocolNotConfigured { proto : (proto) })
+    }
+}
+
+/// Builds the command of the protocol, whose route was looked up
+fn protocol_command(uri : &str, protocol_config : ProtocolConfig, config : &Config) -> Result<Command, ProtoHandlerError> {
+    match protocol_config.kind {
+        HandlerKind::Script => script_command(uri, protocol_config, config),
+        HandlerKind::Exec => exec_command(uri, &protocol_config),
+        kind => Err(ProtoHandlerError::NotACommand { proto : protocol_config.name, kind : kind.to_string() }),
+    }
+}
+
+/// Builds the command running the script of the protocol with its shell
+fn script_command(uri : &str, protocol_config : ProtocolConfig, config : &Config) -> Result<Command, ProtoHandlerError> {
+    let program : String;
+    let mut commandline : Vec<String>;
+    commandline = Vec::new();
+
+    let shell_name = protocol_config.shell.name;
+    debug!("Shell is configured as '{shell_name}'");
+
+    if let Some(shell_config) = lookup_shell(&shell_name, config) {
+        program = shell_config.cmd;
+        commandline.extend(shell_config.args);
+    } else {
+        return Err(ProtoHandlerError::ShellNotConfigured { sh : shell_name });
+    }
+
+    let extra_args = protocol_config.shell.args;
+    if !extra_args.is_empty() {
+        commandline.extend(extra_args);
+    }
+
+    commandline.push(protocol_config.script.name);
+    let script_args = protocol_config.script.args;
+    if !script_args.is_empty() {
+        commandline.extend(script_args);
+    }
+    let mut command = Command::new(program);
+    let quoted_uri = format!("\"{uri}\"");
+    command.args(commandline).arg(quoted_uri);
+    Ok(command)
+}
+
+/// Builds the command running the program of the protocol directly.
+///
+/// The arguments are rendered as templates.  When there are no arguments the
+/// URI is the only argument.
+fn exec_command(uri : &str, protocol_config : &ProtocolConfig) -> Result<Command, ProtoHandlerError> {
+    let Some(exec) = &protocol_config.exec else {
+        return Err(ProtoHandlerError::HandlerNotConfigured {
+            proto : protocol_config.name.clone(),
+            kind : protocol_config.kind.to_string(),
+        });
+    };
+    let parsed = ParsedUri::parse_as(uri, protocol_config.parser)?;
+    let context = template::Context::new(uri, &parsed).with_captures(&protocol_config.captures);
+
+    let mut command = Command::new(&exec.program);
+    if exec.args.is_empty() {
+        command.arg(uri);
+    } else {
+        command.args(exec.args.iter().map(|a| template::render(a, &context)));
+    }
+    Ok(command)
+}
+
+/// Handles the URIs of a protocol
+///
+/// A handler is selected by the `kind` of the protocol configuration, see
+/// `handler_for`.
+pub trait Handler {
+    /// Describes what the handler would do with the URI, without doing it.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the URI could not be handled.
+    fn describe(&self, uri : &str, config : &Config) -> Result<String, ProtoHandlerError>;
+
+    /// Handles the URI, recording what was done in the invocation.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the URI could not be handled.
+    fn handle(&self, uri : &str, invocation : &mut Invocation, config : &Config) -> Result<Outcome, ProtoHandlerError>;
+}
 
-        let extra_args = protocol_config.shell.args;
-        if !extra_args.is_empty() {
-            commandline.extend(extra_args);
+/// Returns the handler for the kind of the protocol.
+///
+/// # Errors
+///
+/// This function will return an error if the configuration of the kind is
+/// missing from the protocol.
+pub fn handler_for(protocol_config : &ProtocolConfig) -> Result<Box<dyn Handler>, ProtoHandlerError> {
+    let not_configured = || ProtoHandlerError::HandlerNotConfigured {
+        proto : protocol_config.name.clone(),
+        kind : protocol_config.kind.to_string(),
+    };
+    Ok(match protocol_config.kind {
+        HandlerKind::Script | HandlerKind::Exec => Box::new(CommandHandler { protocol : protocol_config.clone() }),
+        HandlerKind::AppendFile => Box::new(AppendFileHandler {
+            config : protocol_config.append_file.clone().ok_or_else(not_configured)?,
+            parser : protocol_config.parser,
+            captures : protocol_config.captures.clone(),
+        }),
+        HandlerKind::Http => Box::new(HttpHandler {
+            config : protocol_config.http.clone().ok_or_else(not_configured)?,
+            parser : protocol_config.parser,
+            captures : protocol_config.captures.clone(),
+        }),
+        HandlerKind::Forward => Box::new(ForwardHandler {
+            config : protocol_config.forward.clone().ok_or_else(not_configured)?,
+            parser : protocol_config.parser,
+            captures : protocol_config.captures.clone(),
+        }),
+    })
+}
+
+/// Runs the command of the protocol, as built by `build_command`, for the
+/// `script` and `exec` kinds
+pub struct CommandHandler {
+    /// The configuration of the protocol, with the sections of its route
+    pub protocol : ProtocolConfig,
+}
+
+impl Handler for CommandHandler {
+    fn describe(&self, uri : &str, config : &Config) -> Result<String, ProtoHandlerError> {
+        let command = protocol_command(uri, self.protocol.clone(), config)?;
+        let argv : Vec<String> = std::iter::once(command.get_program())
+            .chain(command.get_args())
+            .map(|a| a.to_string_lossy().into_owned())
+            .collect();
+        Ok(format!("run {}", argv.join(" ")))
+    }
+
+    fn handle(&self, uri : &str, invocation : &mut Invocation, config : &Config) -> Result<Outcome, ProtoHandlerError> {
+        run(protocol_command(uri, self.protocol.clone(), config)?, invocation)
+    }
+}
+
+/// True when the output of the commands is sent to stderr
+static CHILD_OUTPUT_TO_STDERR : AtomicBool = AtomicBool::new(false);
+
+/// Sends the standard output of the commands run from now on to stderr, so
+/// that it does not mix with the output of protoHandler, such as the messages
+/// of the native-messaging host.
+pub fn redirect_child_output() {
+    CHILD_OUTPUT_TO_STDERR.store(true, Ordering::Relaxed);
+}
+
+/// Returns the standard output of a command
+pub(crate) fn child_stdout() -> Stdio {
+    if CHILD_OUTPUT_TO_STDERR.load(Ordering::Relaxed) {
+        Stdio::from(std::io::stderr())
+    } else {
+        Stdio::inherit()
+    }
+}
+
+/// Runs the command and waits for it, recording it in the invocation
+fn run(mut child : Command, invocation : &mut Invocation) -> Result<Outcome, ProtoHandlerError> {
+    let a : Vec<&OsStr> = child.get_args().collect();
+    let a = a
+        .into_iter()
+        .map(|o| o.to_string_lossy())
+        .collect::<Vec<Cow<str>>>()
+        .join(" ");
+    info!("Arguments are {}", redacted(&a));
+    child.stdout(child_stdout());
+    invocation.argv = std::iter::once(child.get_program())
+        .chain(child.get_args())
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect();
+
+    // Run the external cmd
+    let program = child.get_program().to_string_lossy().into_owned();
+    let mut r = child.spawn().map_err(|e| {
+        error!("Failed to run child process");
+        ProtoHandlerError::SpawnError { cmd : program.clone(), reason : e.to_string() }
+    })?;
+    invocation.pid = Some(r.id());
+    if let Some(stdout) = r.stdout.take() {
+        info!("command output {:#?}", stdout);
+    }
+    let status = r
+        .wait()
+        .map_err(|e| ProtoHandlerError::SpawnError { cmd : program, reason : e.to_string() })?;
+    info!("exited with: {status}");
+    Ok(Outcome::from(status))
+}
+
+/// The outcome of a check or policy applied to a URI
+#[derive(Debug, Clone, Serialize, PartialEq)]
+pub struct PolicyCheck {
+    /// What was checked
+    pub name : String,
+    /// True if the check passed
+    pub passed : bool,
+    /// Details about the outcome
+    pub detail : String,
+}
+
+impl fmt::Display for PolicyCheck {
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = if self.passed { "pass" } else { "fail" };
+        write!(f, "[{status}] {}: {}", self.name, self.detail)
+    }
+}
+
+/// The outcome of a handler that ran to completion
+#[derive(Debug, Clone, Copy, Serialize, PartialEq, Eq)]
+pub struct Outcome {
+    /// True if the URI was handled successfully
+    pub success : bool,
+    /// The exit code of the command, or the code the handler maps its result to
+    pub exit_code : Option<i32>,
+    /// True if running the handler again could succeed, e.g. a command that
+    /// exited with an error
+    #[serde(skip)]
+    pub retryable : bool,
+    /// True if no handler ran, because a policy or the fallback rejected the
+    /// URI
+    #[serde(skip)]
+    pub rejected : bool,
+}
+
+impl Outcome {
+    /// The outcome of a handler that succeeded
+    #[must_use] pub fn ok() -> Self {
+        Self { success : true, exit_code : Some(0), retryable : false, rejected : false }
+    }
+
+    /// The outcome of a handler that failed with the given exit code, and
+    /// that is not run again
+    #[must_use] pub fn failed(exit_code : i32) -> Self {
+        Self { success : false, exit_code : Some(exit_code), retryable : false, rejected : false }
+    }
+
+    /// The outcome of a URI rejected with the given exit code before a
+    /// handler ran
+    #[must_use] pub fn rejected(exit_code : i32) -> Self {
+        Self { rejected : true, ..Self::failed(exit_code) }
+    }
+}
+
+impl From<ExitStatus> for Outcome {
+    fn from(status : ExitStatus) -> Self {
+        Self { success : status.success(), exit_code : status.code(), retryable : !status.success(), rejected : false }
+    }
+}
+
+/// The record of handling one URI
+#[derive(Debug)]
+pub struct Invocation {
+    /// A unique id for this invocation
+    pub id : String,
+    /// The URI as it was given
+    pub uri : String,
+    /// When the URI started being handled
+    pub started : OffsetDateTime,
+    /// The protocol found in the URI
+    pub protocol : Option<String>,
+    /// The route within the protocol that handled the URI
+    pub route : Option<String>,
+    /// The URIs the invocation was forwarded to, in order
+    pub forwards : Vec<String>,
+    /// The program and arguments of the command
+    pub argv : Vec<String>,
+    /// The process id of the command
+    pub pid : Option<u32>,
+    /// The policies applied before running the command
+    pub decisions : Vec<PolicyCheck>,
+    /// How many times the handler was run
+    pub attempts : u32,
+    /// How long handling the URI took
+    pub duration : Duration,
+    /// The outcome of the handler, or the error that stopped it; `None` until
+    /// the URI was dispatched
+    pub result : Option<Result<Outcome, ProtoHandlerError>>,
+}
+
+impl Invocation {
+    /// Returns true if the URI was handled successfully
+    #[must_use] pub fn success(&self) -> bool {
+        matches!(&self.result, Some(Ok(outcome)) if outcome.success)
+    }
+
+    /// Returns the exit code of the handler, if it ran to completion
+    #[must_use] pub fn exit_code(&self) -> Option<i32> {
+        self.result.as_ref().and_then(|r| r.as_ref().ok()).and_then(|o| o.exit_code)
+    }
+
+    /// Returns the error that stopped the URI from being handled, or the
+    /// policy that rejected it
+    #[must_use] pub fn error(&self) -> Option<String> {
+        match &self.result {
+            None => Some(String::from("The URI was not handled")),
+            Some(Err(e)) => Some(e.to_string()),
+            Some(Ok(outcome)) if outcome.success => None,
+            Some(Ok(_)) => self.decisions.iter().find(|d| !d.passed).map(|d| d.detail.clone()),
         }
+    }
+}
 
-        commandline.push(protocol_config.script.name);
-        let script_args = protocol_config.script.args;
-        if !script_args.is_empty() {
-            commandline.extend(script_args);
+/// The maximum number of times a URI is forwarded
+pub const MAX_FORWARDS : usize = 8;
+
+/// The name of the protocol handling the schemes that are not configured
+pub const CATCH_ALL : &str = "*";
+
+/// Handles the given URI, waiting for the handler to finish.
+///
+/// # Arguments
+///
+/// * `uri` - A string representing the URI.
+/// * `config` - A reference to the configuration object.
+///
+/// # Returns
+///
+/// * `Invocation` - The record of handling the URI.  Its `result` is set, and
+///   holds the outcome of the handler, or an error if the protocol is not
+///   configured or the handler failed (see `build_command`).
+#[must_use] pub fn run_command(uri : String, config : &Config) -> Invocation {
+    let start = Instant::now();
+    let mut invocation = Invocation {
+        id : Uuid::new_v4().to_string(),
+        uri,
+        started : OffsetDateTime::now_utc(),
+        protocol : None,
+        route : None,
+        forwards : Vec::new(),
+        argv : Vec::new(),
+        pid : None,
+        decisions : Vec::new(),
+        attempts : 0,
+        duration : Duration::ZERO,
+        result : None,
+    };
+    info!("Got uri, building command");
+    let uri = invocation.uri.clone();
+    let protocol_config = get_protocol(&uri, config)
+        .and_then(|proto| lookup_protocol(&proto, config))
+        .unwrap_or_default();
+    let retry = &protocol_config.retry;
+    loop {
+        invocation.attempts += 1;
+        let result = dispatch(&uri, &mut invocation, config);
+        let final_attempt = !retryable(&result);
+        invocation.result = Some(result);
+        if invocation.success() || invocation.attempts >= retry.max_attempts || final_attempt {
+            break;
         }
-        let mut command = Command::new(program);
-        let quoted_uri = format!("\"{uri}\"");
-        command.args(commandline).arg(quoted_uri);
-        Ok(command)
-    } else {
+        let delay = retry.delay(invocation.attempts);
+        warn!(
+            "Attempt {}/{} failed, retrying in {} ms",
+            invocation.attempts,
+            retry.max_attempts,
+            delay.as_millis()
+        );
+        std::thread::sleep(delay);
+        invocation.route = None;
+        invocation.forwards.clear();
+        invocation.argv.clear();
+        invocation.pid = None;
+        invocation.decisions.clear();
+    }
+    let rejected = matches!(&invocation.result, Some(Ok(outcome)) if outcome.rejected);
+    if !invocation.success() && !rejected && retry.dead_letter {
+        match queue::push(&invocation, config) {
+            Ok(letter) => {
+                warn!("Stored {} in the dead-letter queue as {}", redacted(&uri), letter.id);
+                invocation.decisions.push(PolicyCheck {
+                    name : String::from("dead-letter"),
+                    passed : true,
+                    detail : format!("queued after {} attempts", invocation.attempts),
+                });
+            },
+            Err(e) => error!("{}", redacted(&e.to_string())),
+        }
+    }
+    invocation.duration = start.elapsed();
+    notify::notify(&invocation, &protocol_config.notify, config);
+    invocation
+}
+
+/// Returns true if running the handler again could succeed.
+///
+/// Only the failures of a command and IO errors are retried.  URIs rejected by
+/// a policy or the fallback are not, nor are those of the `http` kind, which
+/// retries the requests itself, see `HttpConfig::retries`.
+fn retryable(result : &Result<Outcome, ProtoHandlerError>) -> bool {
+    match result {
+        Ok(outcome) => outcome.retryable,
+        Err(e) => matches!(
+            e,
+            ProtoHandlerError::SpawnError { .. }
+                | ProtoHandlerError::AppendError { .. }
+                | ProtoHandlerError::LockError { .. }
+                | ProtoHandlerError::StateError { .. }
+        ),
+    }
+}
+
+/// Handles the URI with the handler of its protocol.
+///
+/// This is used by `run_command`, and by handlers that forward the URI.
+///
+/// # Errors
+///
+/// This function will return an error if the URI does not contain a
+/// recognized protocol, the protocol is not configured, or the handler failed.
+pub fn dispatch(uri : &str, invocation : &mut Invocation, config : &Config) -> Result<Outcome, ProtoHandlerError> {
+    let Some(proto) = get_protocol(uri, config) else {
+        return Err(ProtoHandlerError::UriParseError { uri : uri.to_string() });
+    };
+    if invocation.protocol.is_none() {
+        invocation.protocol = Some(proto.clone());
+    }
+    let Some(protocol_config) = lookup_protocol(&proto, config) else {
         info!("protocol '{proto}' not configured");
-        Err(ProtoHandlerError::ProtocolNotConfigured { proto : (proto) })
+        return fallback(uri, proto, invocation, config);
+    };
+
+    let rewritten = rewrite::apply(uri, &protocol_config.rewrite, protocol_config.parser)?;
+    if rewritten != uri && get_protocol(&rewritten, config).as_ref() != Some(&proto) {
+        return forward(&rewritten, invocation, config);
+    }
+    let (protocol_config, route) = lookup_route(protocol_config, &rewritten);
+    if invocation.route.is_none() {
+        invocation.route.clone_from(&route);
+    }
+    if let Some(elapsed) = dedupe::check(&protocol_config, &rewritten, config)? {
+        let detail = format!("skipped, an identical URI was received {elapsed} seconds ago");
+        info!("{} {detail}", redacted(&rewritten));
+        invocation.decisions.push(PolicyCheck { name : String::from("dedupe"), passed : false, detail });
+        return Ok(Outcome::ok());
     }
+    let result = admit(&protocol_config, &rewritten, invocation, config);
+    if !matches!(&result, Ok(outcome) if outcome.success) {
+        // Not handled, an identical URI must not be skipped
+        if let Err(e) = dedupe::forget(&protocol_config, &rewritten, config) {
+            warn!("{}", redacted(&e.to_string()));
+        }
+    }
+    result
+}
+
+/// Handles the URI with the handler of its protocol, if its concurrency policy
+/// admits it
+fn admit(protocol_config : &ProtocolConfig, uri : &str, invocation : &mut Invocation, config : &Config) -> Result<Outcome, ProtoHandlerError> {
+    let mut permit = match concurrency::admit(protocol_config, uri, config)? {
+        Admission::Admitted(permit) => {
+            if protocol_config.concurrency.mode != ConcurrencyMode::Unlimited {
+                let detail = format!("admitted ({})", protocol_config.concurrency.mode);
+                invocation.decisions.push(PolicyCheck { name : String::from("concurrency"), passed : true, detail });
+            }
+            permit
+        },
+        Admission::Rejected(reason) => {
+            warn!("Rejecting {}: {reason}", redacted(uri));
+            invocation.decisions.push(PolicyCheck { name : String::from("concurrency"), passed : false, detail : reason });
+            return Ok(Outcome::rejected(protocol_config.concurrency.exit_code));
+        },
+    };
+    debug!("Handling '{}' with the {} handler", protocol_config.name, protocol_config.kind);
+    let result = handler_for(protocol_config).and_then(|handler| handler.handle(uri, invocation, config));
+    if !matches!(&result, Ok(outcome) if outcome.success) {
+        // Not handled, a retry or an identical URI must not be dropped
+        if let Err(e) = permit.forget() {
+            warn!("{}", redacted(&e.to_string()));
+        }
+    }
+    result
+}
+
+/// Handles a URI whose scheme is not configured, as set in `fallback`.
+fn fallback(uri : &str, proto : String, invocation : &mut Invocation, config : &Config) -> Result<Outcome, ProtoHandlerError> {
+    let fallback = &config.fallback;
+    match fallback.action {
+        FallbackAction::Log => Err(ProtoHandlerError::ProtocolNotConfigured { proto }),
+        FallbackAction::Notify => {
+            let notification = Notification {
+                title : String::from("protoHandler"),
+                body : format!("No protocol configured for '{}'", redacted(uri)),
+                critical : true,
+            };
+            if let Err(e) = notify::send(&notification, config) {
+                warn!("{e}");
+            }
+            Err(ProtoHandlerError::ProtocolNotConfigured { proto })
+        },
+        FallbackAction::Open => {
+            info!("Handing {} off to {}", redacted(uri), fallback.open_program);
+            let mut command = Command::new(&fallback.open_program);
+            command.arg(uri);
+            run(command, invocation)
+        },
+        FallbackAction::Forward => match &fallback.uri {
+            Some(template) => {
+                let parsed = ParsedUri::parse(uri)?;
+                forward(&template::render(template, &template::Context::new(uri, &parsed)), invocation, config)
+            },
+            None => Err(ProtoHandlerError::ProtocolNotConfigured { proto }),
+        },
+        FallbackAction::Exit => {
+            warn!("protocol '{proto}' not configured, exiting with {}", fallback.exit_code);
+            Ok(if fallback.exit_code == 0 { Outcome::ok() } else { Outcome::rejected(fallback.exit_code) })
+        },
+    }
+}
+
+/// Handles a URI that the invocation was forwarded to, by a rewrite rule or by
+/// the `forward` kind.
+///
+/// # Errors
+///
+/// This function will return an error if the URI was already handled by the
+/// invocation, if it was forwarded more than `MAX_FORWARDS` times, or if it
+/// could not be handled, see `dispatch`.
+pub fn forward(uri : &str, invocation : &mut Invocation, config : &Config) -> Result<Outcome, ProtoHandlerError> {
+    if uri == invocation.uri
+        || invocation.forwards.iter().any(|f| f == uri)
+        || invocation.forwards.len() >= MAX_FORWARDS
+    {
+        return Err(ProtoHandlerError::ForwardLoop { uri : redacted(uri) });
+    }
+    info!("Forwarding to {}", redacted(uri));
+    invocation.forwards.push(uri.to_string());
+    dispatch(uri, invocation, config)
 }
 
 /// Extracts the protocol from the given URI.
 ///
 /// This function uses a regular expression to find the protocol at the beginning of the URI.
+/// The protocol must be followed by `://`, unless the protocol is configured
+/// with a parser for URIs without an authority, such as `mailto:`.
 ///
 /// # Arguments
 ///
 /// * `uri` - A reference to a string representing the URI.
+/// * `config` - A reference to the configuration object.
 ///
 /// # Returns
 ///
 /// * `Some(String)` - If the protocol is found.
 /// * `None` - If the protocol is not recognized.
 ///
+/// # Panics
+///
+/// Panics if the protocol regular expression is invalid.
+///
 /// # Examples
 ///
 /// ```
+/// use protohandlers::config::{Config, ProtocolConfig, UriParser};
+/// use protohandlers::runner::get_protocol;
+///
+/// let mut config = Config::new();
 /// let uri = "http://example.com".to_string();
-/// let protocol = get_protocol(&uri);
+/// let protocol = get_protocol(&uri, &config);
 /// assert_eq!(protocol, Some("http".to_string()));
+/// assert_eq!(get_protocol("mailto:me@example.com", &config), None);
+///
+/// config.protocols.push(ProtocolConfig {
+///     name : String::from("mailto"),
+///     parser : UriParser::Mailto,
+///     ..ProtocolConfig::default()
+/// });
+/// assert_eq!(get_protocol("mailto:me@example.com", &config), Some("mailto".to_string()));
 /// ```
-
-#[must_use] pub fn get_protocol(uri : &String) -> Option<String> {
+#[must_use] pub fn get_protocol(uri : &str, config : &Config) -> Option<String> {
     // attempt to find the protocol string at the begining of the uri
-    let re = Regex::new(r"^(?<proto>[a-z][a-zA-Z0-9-_]+):\/\/").unwrap();
+    let re = Regex::new(r"^(?<proto>[a-z][a-zA-Z0-9-_]+):(?<slashes>//)?").unwrap();
     if let Some(p) = re.captures(uri) {
         let proto = p["proto"].to_string();
-        Some(proto)
-    } else {
-        info!("protocol not recognized {uri}");
-        None
+        let opaque = || config.protocols.iter().any(|c| c.name == proto && c.parser.is_opaque());
+        if p.name("slashes").is_some() || opaque() {
+            return Some(proto);
+        }
     }
+    info!("protocol not recognized {}", redacted(uri));
+    None
 }
 
 /// Looks up the protocol configuration in the given configuration object.
 ///
-/// This function searches for the protocol configuration by name.
+/// This function searches for the protocol configuration by name, and falls
+/// back to the catch-all protocol named `*`.
 ///
 /// # Arguments
 ///
@@ -131,18 +686,54 @@ pub fn build_command(uri : String, config : Config) -> Result<Command, ProtoHand
 /// # Examples
 ///
 /// ```
-/// let proto = "http".to_string();
-/// let config = Config::new();
+/// use protohandlers::config::{Config, ProtocolConfig};
+/// use protohandlers::runner::lookup_protocol;
+///
+/// let proto = "snip-proto".to_string();
+/// let mut config = Config::new();
+/// assert!(lookup_protocol(&proto, &config).is_none());
+///
+/// config.protocols.push(ProtocolConfig { name : proto.clone(), ..ProtocolConfig::default() });
 /// let protocol_config = lookup_protocol(&proto, &config);
 /// assert!(protocol_config.is_some());
 /// ```
-
 #[must_use] pub fn lookup_protocol(proto : &String, config : &Config) -> Option<ProtocolConfig> {
     config
         .protocols
-        .clone()
-        .into_iter()
+        .iter()
         .find(|p| p.name == *proto)
+        .or_else(|| config.protocols.iter().find(|p| p.name == CATCH_ALL))
+        .cloned()
+}
+
+/// Looks up the route of the protocol handling the URI.
+///
+/// # Arguments
+///
+/// * `protocol_config` - The configuration of the protocol of the URI.
+/// * `uri` - A string slice representing the URI.
+///
+/// # Returns
+///
+/// * `(ProtocolConfig, Some(String))` - The configuration of the protocol with
+///   the sections of the route and the values it captured, and the name of the
+///   route, see `RouteConfig::name`.
+/// * `(ProtocolConfig, None)` - The configuration of the protocol, if no route
+///   handles the URI.
+#[must_use] pub fn lookup_route(protocol_config : ProtocolConfig, uri : &str) -> (ProtocolConfig, Option<String>) {
+    let Ok(parsed) = ParsedUri::parse_as(uri, protocol_config.parser) else {
+        return (protocol_config, None);
+    };
+    match protocol_config.route(&parsed) {
+        Some(matched) => {
+            let name = matched.route.name();
+            debug!("Routing '{}' to the '{name}' route", parsed.subcommand);
+            let mut routed = protocol_config.with_route(matched.route);
+            routed.captures = matched.captures;
+            (routed, Some(name))
+        },
+        None => (protocol_config, None),
+    }
 }
 
 /// Looks up the shell configuration in the given configuration object.
@@ -162,12 +753,15 @@ pub fn build_command(uri : String, config : Config) -> Result<Command, ProtoHand
 /// # Examples
 ///
 /// ```
-/// let name = "bash".to_string();
+/// use protohandlers::config::Config;
+/// use protohandlers::runner::lookup_shell;
+///
+/// let name = "pwsh".to_string();
 /// let config = Config::new();
 /// let shell_config = lookup_shell(&name, &config);
 /// assert!(shell_config.is_some());
+/// assert!(lookup_shell(&"bash".to_string(), &config).is_none());
 /// ```
-
 #[must_use] pub fn lookup_shell(name : &String, config : &Config) -> Option<ShellConfig> {
     debug!("Looking up configuration for shell '{name}'");
     config.shells.clone().into_iter().find(|s| s.name == *name)
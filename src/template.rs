@@ -0,0 +1,172 @@
+//! Rendering of the templates used by the built-in handlers.
+//!
+//! A template is text with placeholders in braces, replaced by the components
+//! of the URI being handled:
+//!
+//! ```text
+//! {uri} {scheme} {subcommand} {path} {fragment} {query} {<parameter name>}
+//! ```
+//!
+//! The time the URI is handled is available as `{date}`, `{time}` and `{now}`,
+//! which take an optional strftime format such as `{date:%Y-%m-%d}`.
+//!
+//! A placeholder can be followed by filters, separated by `|`:
+//!
+//! * `urlencode` - percent-encodes the value
+//! * `trim` - removes the whitespace around the value
+//! * `default:<text>` - replaces an empty value with `<text>`
+//!
+//! Placeholders that match nothing are replaced by an empty string.  Use `{{`
+//! and `}}` for literal braces.
+
+use simplelog::warn;
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::routing::Capture;
+use crate::uri::{encode, ParsedUri};
+
+/// The values available to a template
+#[derive(Debug, Clone)]
+pub struct Context<'a> {
+    /// The URI as it was given
+    pub uri : &'a str,
+    /// The components of the URI
+    pub parsed : &'a ParsedUri,
+    /// The time the URI is handled
+    pub now : OffsetDateTime,
+    /// Extra values, taking precedence over the components of the URI
+    pub vars : Vec<(String, String)>,
+}
+
+impl<'a> Context<'a> {
+    /// Creates the context of a URI, handled now in the local time zone
+    #[must_use] pub fn new(uri : &'a str, parsed : &'a ParsedUri) -> Self {
+        let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+        Self { uri, parsed, now, vars : Vec::new() }
+    }
+
+    /// Adds an extra value to the context
+    #[must_use] pub fn with(mut self, name : &str, value : impl Into<String>) -> Self {
+        self.vars.push((name.to_string(), value.into()));
+        self
+    }
+
+    /// Adds the values captured by the route handling the URI
+    #[must_use] pub fn with_captures(self, captures : &[Capture]) -> Self {
+        captures.iter().fold(self, |context, c| context.with(&c.name, c.value.clone()))
+    }
+
+    /// Returns the value of a placeholder, if there is one.
+    ///
+    /// `format` is the strftime format of the `date`, `time` and `now`
+    /// placeholders.
+    #[must_use] pub fn value(&self, name : &str, format : Option<&str>) -> Option<String> {
+        if let Some((_, value)) = self.vars.iter().find(|(n, _)| n == name) {
+            return Some(value.clone());
+        }
+        match name {
+            "uri" => Some(self.uri.to_string()),
+            "scheme" => Some(self.parsed.scheme.clone()),
+            "subcommand" => Some(self.parsed.subcommand.clone()),
+            "path" => Some(self.parsed.path.clone()),
+            "fragment" => self.parsed.fragment.clone(),
+            "query" => Some(self.parsed.query_string()),
+            "date" => self.timestamp(format.unwrap_or("%Y-%m-%d")),
+            "time" => self.timestamp(format.unwrap_or("%H:%M:%S")),
+            "now" => match format {
+                Some(format) => self.timestamp(format),
+                None => self.now.format(&Rfc3339).ok(),
+            },
+            name => self.parsed.param(name).map(ToString::to_string),
+        }
+    }
+
+    /// Formats the time with the strftime format
+    fn timestamp(&self, format : &str) -> Option<String> {
+        let formatted = time::format_description::parse_strftime_borrowed(format)
+            .map_err(|e| e.to_string())
+            .and_then(|items| self.now.format(&items).map_err(|e| e.to_string()));
+        match formatted {
+            Ok(text) => Some(text),
+            Err(e) => {
+                warn!("Ignoring invalid time format '{format}': {e}");
+                None
+            },
+        }
+    }
+}
+
+/// Applies a filter to the value of a placeholder
+fn apply(filter : &str, value : String) -> String {
+    let (name, arg) = filter.split_once(':').unwrap_or((filter, ""));
+    match name.trim() {
+        "urlencode" => encode(&value),
+        "trim" => value.trim().to_string(),
+        "default" if value.is_empty() => arg.to_string(),
+        "default" => value,
+        name => {
+            warn!("Ignoring unknown template filter '{name}'");
+            value
+        },
+    }
+}
+
+/// Renders one placeholder, the text between the braces
+fn placeholder(text : &str, context : &Context) -> String {
+    let mut parts = text.split('|');
+    let head = parts.next().unwrap_or_default();
+    let (name, format) = match head.split_once(':') {
+        Some((name, format)) => (name.trim(), Some(format)),
+        None => (head.trim(), None),
+    };
+    let value = context.value(name, format).unwrap_or_default();
+    parts.fold(value, |value, filter| apply(filter, value))
+}
+
+/// Renders the template with the values of the context.
+///
+/// # Arguments
+///
+/// * `template` - The template text.
+/// * `context` - The values of the placeholders.
+///
+/// # Returns
+///
+/// * `String` - The template with its placeholders replaced.
+///
+/// # Examples
+///
+/// ```
+/// use protohandlers::template::{render, Context};
+/// use protohandlers::ParsedUri;
+///
+/// let uri = "snip-proto://capture?title=Get%20started";
+/// let parsed = ParsedUri::parse(uri).unwrap();
+/// let context = Context::new(uri, &parsed);
+/// let line = render("- {title} ({subcommand}) {{x}}", &context);
+/// assert_eq!("- Get started (capture) {x}", line);
+///
+/// let line = render("{title|urlencode} {body|default:no body}", &context);
+/// assert_eq!("Get%20started no body", line);
+/// ```
+#[must_use] pub fn render(template : &str, context : &Context) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        out.push_str(&rest[..i]);
+        let tail = &rest[i..];
+        if tail.starts_with("{{") || tail.starts_with("}}") {
+            out.push_str(&tail[..1]);
+            rest = &tail[2..];
+        } else if let (true, Some(end)) = (tail.starts_with('{'), tail.find('}')) {
+            out.push_str(&placeholder(&tail[1..end], context));
+            rest = &tail[end + 1..];
+        } else {
+            out.push_str(&tail[..1]);
+            rest = &tail[1..];
+        }
+    }
+    out.push_str(rest);
+    out
+}
@@ -1 +1,20 @@
+mod audit;
+mod concurrency;
 mod config;
+#[cfg(unix)]
+mod daemon;
+mod dedupe;
+mod explain;
+mod fallback;
+mod handlers;
+mod history;
+mod http;
+mod logging;
+mod native;
+mod notify;
+mod queue;
+mod redact;
+mod rewrite;
+mod routing;
+mod template;
+mod uri;
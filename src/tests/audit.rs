@@ -0,0 +1,58 @@
+#[cfg(test)]
+use crate::audit::record;
+#[cfg(test)]
+use crate::config::Config;
+#[cfg(test)]
+use crate::runner::run_command;
+
+#[test]
+fn records_one_json_line_per_invocation() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let mut config = Config::new();
+    config.audit.enabled = true;
+    config.audit.path = Some(path.display().to_string());
+
+    for uri in ["unknown://capture?token=abc", "unknown://other"] {
+        let invocation = run_command(String::from(uri), &config);
+        record(&invocation, &config).unwrap();
+    }
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    let lines : Vec<serde_json::Value> = content.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+    assert_eq!(2, lines.len());
+    assert_eq!("unknown://capture?token=REDACTED", lines[0]["uri"]);
+    assert_eq!("unknown", lines[0]["protocol"]);
+    assert_eq!(false, lines[0]["success"]);
+    assert!(lines[0]["error"].as_str().unwrap().contains("not configured"));
+    assert_ne!(lines[0]["id"], lines[1]["id"]);
+}
+
+#[test]
+fn errors_are_redacted() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let mut config = Config::new();
+    config.audit.enabled = true;
+    config.audit.path = Some(path.display().to_string());
+
+    let invocation = run_command(String::from("no protocol?token=abc"), &config);
+    assert!(invocation.error().unwrap().contains("token=abc"));
+    record(&invocation, &config).unwrap();
+
+    let content = std::fs::read_to_string(&path).unwrap();
+    assert!(!content.contains("abc"));
+    assert!(content.contains("token=REDACTED"));
+}
+
+#[test]
+fn disabled_audit_writes_nothing() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("audit.jsonl");
+    let mut config = Config::new();
+    config.audit.path = Some(path.display().to_string());
+
+    let invocation = run_command(String::from("unknown://capture"), &config);
+    record(&invocation, &config).unwrap();
+    assert!(!path.exists());
+}
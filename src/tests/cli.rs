@@ -0,0 +1,76 @@
+#[cfg(test)]
+use clap::Parser;
+#[cfg(test)]
+use clap_complete::CompletionCandidate;
+#[cfg(test)]
+use crate::cli::{protocol_candidates, uri_candidates, Cli, Command, ConfigCommand, NativeCommand};
+#[cfg(test)]
+use protohandlers::config::{Config, ProtocolConfig, UriParser};
+
+#[test]
+fn bare_uri() {
+    let cli = Cli::try_parse_from(["protohandlers", "snip-proto://capture?template=c"]).unwrap();
+    assert!(cli.command.is_none());
+    assert_eq!(vec![String::from("snip-proto://capture?template=c")], cli.uris);
+}
+
+#[test]
+fn open_subcommand() {
+    let cli = Cli::try_parse_from(["protohandlers", "open", "snip-proto://capture"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::Open { dry_run : false, .. })));
+    assert!(cli.uris.is_empty());
+}
+
+#[test]
+fn global_options_before_subcommand() {
+    let cli = Cli::try_parse_from(["protohandlers", "-c", "other.yml", "config", "show"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::Config(ConfigCommand::Show))));
+    assert_eq!(Some(String::from("other.yml")), cli.config_file);
+}
+
+#[test]
+fn no_arguments_is_an_error() {
+    assert!(Cli::try_parse_from(["protohandlers"]).is_err());
+    let err = Cli::try_parse_from(["protohandlers", "-c", "other.yml"]).unwrap_err();
+    assert_eq!(clap::error::ErrorKind::MissingRequiredArgument, err.kind());
+    assert!(Cli::try_parse_from(["protohandlers", "-u", "snip-proto://capture"]).is_ok());
+}
+
+#[test]
+fn several_uris() {
+    let cli = Cli::try_parse_from(["protohandlers", "snip-proto://one", "snip-proto://two"]).unwrap();
+    assert_eq!(2, cli.uris.len());
+
+    let cli = Cli::try_parse_from(["protohandlers", "open", "snip-proto://one", "snip-proto://two"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::Open { uris, .. }) if uris.len() == 2));
+}
+
+#[test]
+fn completes_configured_protocols() {
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        name : String::from("snip-proto"),
+        ..ProtocolConfig::default()
+    });
+    config.protocols.push(ProtocolConfig {
+        name : String::from("mailto"),
+        parser : UriParser::Mailto,
+        ..ProtocolConfig::default()
+    });
+    config.protocols.push(ProtocolConfig { name : String::from("*"), ..ProtocolConfig::default() });
+
+    let values = |candidates : Vec<CompletionCandidate>| -> Vec<String> {
+        candidates.iter().map(|c| c.get_value().to_string_lossy().into_owned()).collect()
+    };
+    assert_eq!(vec!["snip-proto", "mailto", "*"], values(protocol_candidates(&config)));
+    assert_eq!(vec!["snip-proto://", "mailto:"], values(uri_candidates(&config)));
+}
+
+#[test]
+fn native_host_accepts_browser_arguments() {
+    let cli = Cli::try_parse_from(["protohandlers", "native", "host", "/path/manifest.json", "capture@example.org"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::Native(NativeCommand::Host { args })) if args.len() == 2));
+
+    let cli = Cli::try_parse_from(["protohandlers", "native", "host", "chrome-extension://abc/"]).unwrap();
+    assert!(matches!(cli.command, Some(Command::Native(NativeCommand::Host { .. }))));
+}
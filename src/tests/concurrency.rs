@@ -0,0 +1,106 @@
+#[cfg(test)]
+use std::path::Path;
+
+#[cfg(test)]
+use crate::concurrency::{admit, Admission};
+#[cfg(test)]
+use crate::config::{AppendFileConfig, ConcurrencyConfig, ConcurrencyMode, Config, HandlerKind, ProtocolConfig};
+#[cfg(test)]
+use crate::runner::run_command;
+
+#[cfg(test)]
+fn limited(dir : &Path, concurrency : ConcurrencyConfig) -> (Config, ProtocolConfig) {
+    let mut config = Config::new();
+    config.runtime_dir = Some(dir.display().to_string());
+    let protocol = ProtocolConfig {
+        name : String::from("snip-proto"),
+        kind : HandlerKind::AppendFile,
+        append_file : Some(AppendFileConfig {
+            path : dir.join("inbox.md").display().to_string(),
+            template : String::from("{subcommand}"),
+        }),
+        concurrency,
+        ..ProtocolConfig::default()
+    };
+    config.protocols.push(protocol.clone());
+    (config, protocol)
+}
+
+#[test]
+fn max_rejects_when_all_slots_are_taken() {
+    let dir = tempfile::tempdir().unwrap();
+    let concurrency = ConcurrencyConfig { mode : ConcurrencyMode::Max, max : 2, ..ConcurrencyConfig::default() };
+    let (config, protocol) = limited(dir.path(), concurrency);
+
+    let first = admit(&protocol, "snip-proto://a", &config).unwrap();
+    let second = admit(&protocol, "snip-proto://b", &config).unwrap();
+    assert!(matches!(first, Admission::Admitted(_)));
+    assert!(matches!(second, Admission::Admitted(_)));
+    assert!(matches!(admit(&protocol, "snip-proto://c", &config).unwrap(), Admission::Rejected(_)));
+
+    let invocation = run_command(String::from("snip-proto://c"), &config);
+    assert!(!invocation.success());
+    assert_eq!(Some(75), invocation.exit_code());
+    assert!(invocation.error().unwrap().contains("already being handled"));
+
+    drop(first);
+    assert!(run_command(String::from("snip-proto://c"), &config).success());
+    drop(second);
+}
+
+#[test]
+fn drop_duplicates_rejects_within_window() {
+    let dir = tempfile::tempdir().unwrap();
+    let concurrency = ConcurrencyConfig {
+        mode : ConcurrencyMode::DropDuplicates,
+        window : 60,
+        exit_code : 3,
+        ..ConcurrencyConfig::default()
+    };
+    let (config, protocol) = limited(dir.path(), concurrency);
+
+    let first = admit(&protocol, "snip-proto://a", &config).unwrap();
+    assert!(matches!(first, Admission::Admitted(_)));
+    assert!(matches!(admit(&protocol, "snip-proto://a", &config).unwrap(), Admission::Rejected(_)));
+    drop(first);
+
+    let invocation = run_command(String::from("snip-proto://a"), &config);
+    assert_eq!(Some(3), invocation.exit_code());
+    assert!(invocation.decisions.iter().any(|d| d.name == "concurrency" && !d.passed));
+    assert!(run_command(String::from("snip-proto://b"), &config).success());
+    assert_eq!("b\n", std::fs::read_to_string(dir.path().join("inbox.md")).unwrap());
+}
+
+#[test]
+fn drop_duplicates_forgets_failures_and_prunes_stale_locks() {
+    let dir = tempfile::tempdir().unwrap();
+    let concurrency = ConcurrencyConfig { mode : ConcurrencyMode::DropDuplicates, window : 60, ..ConcurrencyConfig::default() };
+    let (config, _) = limited(dir.path(), concurrency);
+    let stale = dir.path().join("locks").join("snip-proto.0123456789abcdef.lock");
+    std::fs::create_dir_all(stale.parent().unwrap()).unwrap();
+    std::fs::write(&stale, "").unwrap();
+    std::fs::create_dir(dir.path().join("inbox.md")).unwrap();
+
+    let invocation = run_command(String::from("snip-proto://a"), &config);
+    assert!(!invocation.success());
+    assert!(!stale.exists());
+    let invocation = run_command(String::from("snip-proto://a"), &config);
+    assert!(invocation.decisions.iter().all(|d| d.passed));
+
+    std::fs::remove_dir(dir.path().join("inbox.md")).unwrap();
+    assert!(run_command(String::from("snip-proto://a"), &config).success());
+    assert_eq!(1, std::fs::read_dir(dir.path().join("locks")).unwrap().count());
+}
+
+#[test]
+fn serialize_admits_one_at_a_time() {
+    let dir = tempfile::tempdir().unwrap();
+    let concurrency = ConcurrencyConfig { mode : ConcurrencyMode::Serialize, ..ConcurrencyConfig::default() };
+    let (config, protocol) = limited(dir.path(), concurrency);
+
+    let invocation = run_command(String::from("snip-proto://a"), &config);
+    assert!(invocation.success());
+    assert!(invocation.decisions.iter().any(|d| d.name == "concurrency" && d.passed));
+    assert!(matches!(admit(&protocol, "snip-proto://a", &config).unwrap(), Admission::Admitted(_)));
+    assert!(dir.path().join("locks").join("snip-proto.lock").exists());
+}
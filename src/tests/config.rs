@@ -11,6 +11,18 @@ fn first_default_shells() {
     assert!(shells[0].args.len() == 3);
 }
 
+#[test]
+fn invalid_config_is_an_error() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("protohandler.yml");
+    std::fs::write(&path, "protocols: [\n").unwrap();
+
+    let mut config = Config::new();
+    let error = config.load(path.display().to_string()).unwrap_err();
+    assert!(error.to_string().starts_with("Could not parse config file"));
+    assert!(config.load(dir.path().join("missing.yml").display().to_string()).is_err());
+}
+
 mod shells {
     use crate::config::ShellConfig;
 
@@ -22,3 +34,28 @@ mod shells {
         assert_eq!(0, shell.args.len());
     }
 }
+
+mod batch {
+    use crate::config::{BatchMode, Config};
+
+    #[test]
+    fn test_batch_defaults_to_sequential() {
+        let yaml = "logging:\n  path: test.log\n  level: info\nshells: []\nprotocols: []\n";
+        let config : Config = serde_yml::from_str(yaml).unwrap();
+        assert_eq!(BatchMode::Sequential, config.batch.mode);
+    }
+}
+
+mod schema {
+    use crate::config::Config;
+
+    #[test]
+    fn test_committed_schema_is_current() {
+        let committed = include_str!("../../protohandler.schema.json");
+        assert_eq!(
+            committed.trim_end(),
+            Config::schema(),
+            "protohandler.schema.json is out of date, regenerate it with `protohandlers config schema`"
+        );
+    }
+}
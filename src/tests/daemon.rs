@@ -0,0 +1,65 @@
+#[cfg(test)]
+use std::thread;
+#[cfg(test)]
+use std::time::Duration;
+
+#[cfg(test)]
+use crate::daemon::{connect, request, serve, Request, Status};
+#[cfg(test)]
+use crate::error::ProtoHandlerError;
+
+#[cfg(test)]
+const TIMEOUT : Duration = Duration::from_secs(5);
+
+#[test]
+fn handles_requests_until_stopped() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("run").join("protohandler.sock");
+    let server = {
+        let path = path.clone();
+        thread::spawn(move || serve(&path, |uris| uris.into_iter().map(|uri| uri.len()).collect::<Vec<usize>>()))
+    };
+    while connect(&path).is_err() {
+        thread::sleep(Duration::from_millis(10));
+    }
+    assert!(serve(&path, |uris : Vec<String>| uris).is_err());
+
+    let lengths : Vec<usize> = request(connect(&path).unwrap(), &Request::Open { uris : vec![String::from("tt://one")] }, TIMEOUT).unwrap();
+    assert_eq!(vec![8], lengths);
+    let status : Status = request(connect(&path).unwrap(), &Request::Status, TIMEOUT).unwrap();
+    assert_eq!(std::process::id(), status.pid);
+
+    let _ : Status = request(connect(&path).unwrap(), &Request::Stop, TIMEOUT).unwrap();
+    server.join().unwrap().unwrap();
+    assert!(!path.exists());
+    assert!(connect(&path).is_err());
+}
+
+#[test]
+fn slow_clients_and_replies_do_not_block() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("protohandler.sock");
+    let server = {
+        let path = path.clone();
+        thread::spawn(move || {
+            serve(&path, |uris : Vec<String>| {
+                thread::sleep(Duration::from_secs(1));
+                uris
+            })
+        })
+    };
+    while connect(&path).is_err() {
+        thread::sleep(Duration::from_millis(10));
+    }
+
+    let silent = connect(&path).unwrap();
+    let status : Status = request(connect(&path).unwrap(), &Request::Status, Duration::from_millis(500)).unwrap();
+    assert_eq!(std::process::id(), status.pid);
+    let open = Request::Open { uris : vec![String::from("tt://one")] };
+    let slow : Result<Vec<String>, _> = request(connect(&path).unwrap(), &open, Duration::from_millis(100));
+    assert!(matches!(slow, Err(ProtoHandlerError::DaemonTimeout { .. })));
+    drop(silent);
+
+    let _ : Status = request(connect(&path).unwrap(), &Request::Stop, TIMEOUT).unwrap();
+    server.join().unwrap().unwrap();
+}
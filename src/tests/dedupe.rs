@@ -0,0 +1,67 @@
+#[cfg(test)]
+use crate::config::{AppendFileConfig, Config, ExecConfig, HandlerKind, ProtocolConfig};
+#[cfg(test)]
+use crate::dedupe::{canonicalize, check, state_path};
+#[cfg(test)]
+use crate::explain::explain;
+#[cfg(test)]
+use crate::runner::run_command;
+
+#[test]
+fn canonical_uris_ignore_parameter_order_and_encoding() {
+    assert_eq!("snip-proto://capture?a=1&b=2", canonicalize("SNIP-PROTO://capture/?b=2&a=%31"));
+    assert_ne!(canonicalize("snip-proto://capture?a=1"), canonicalize("snip-proto://capture?a=2"));
+}
+
+#[test]
+fn identical_uris_within_the_window_are_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let inbox = dir.path().join("inbox.md");
+    let mut config = Config::new();
+    config.state_dir = Some(dir.path().display().to_string());
+    config.protocols.push(ProtocolConfig {
+        name : String::from("snip-proto"),
+        kind : HandlerKind::AppendFile,
+        append_file : Some(AppendFileConfig {
+            path : inbox.display().to_string(),
+            template : String::from("{title}"),
+        }),
+        dedupe_window : 60,
+        ..ProtocolConfig::default()
+    });
+
+    assert!(run_command(String::from("snip-proto://capture?title=a&url=x"), &config).decisions.is_empty());
+    let invocation = run_command(String::from("snip-proto://capture/?url=x&title=a"), &config);
+    assert!(invocation.success());
+    assert!(invocation.error().is_none());
+    assert!(invocation.decisions.iter().any(|d| d.name == "dedupe" && d.detail.contains("skipped")));
+    assert!(run_command(String::from("snip-proto://capture?title=b"), &config).success());
+    assert_eq!("a\nb\n", std::fs::read_to_string(&inbox).unwrap());
+
+    let state = std::fs::read_to_string(state_path(&config)).unwrap();
+    assert!(!state.contains("capture"));
+    assert!(explain("snip-proto://capture", &config).checks.iter().any(|c| c.name == "dedupe"));
+
+    config.protocols[0].dedupe_window = 0;
+    assert_eq!(None, check(&config.protocols[0], "snip-proto://capture?title=a&url=x", &config).unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_uris_are_not_skipped() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new();
+    config.state_dir = Some(dir.path().display().to_string());
+    config.protocols.push(ProtocolConfig {
+        name : String::from("snip-proto"),
+        kind : HandlerKind::Exec,
+        exec : Some(ExecConfig { program : String::from("sh"), args : vec![String::from("-c"), String::from("exit 3")] }),
+        dedupe_window : 60,
+        ..ProtocolConfig::default()
+    });
+
+    assert_eq!(Some(3), run_command(String::from("snip-proto://capture"), &config).exit_code());
+    let invocation = run_command(String::from("snip-proto://capture"), &config);
+    assert_eq!(Some(3), invocation.exit_code());
+    assert!(!invocation.decisions.iter().any(|d| d.name == "dedupe"));
+}
@@ -0,0 +1,47 @@
+#[cfg(test)]
+use crate::config::{Config, ProtocolConfig, ProtocolScriptConfig, ProtocolShellConfig};
+#[cfg(test)]
+use crate::explain::explain;
+
+#[cfg(test)]
+fn config_with_protocol() -> Config {
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        name : String::from("snip-proto"),
+        desc : String::from("A web snipping protocol"),
+        script : ProtocolScriptConfig {
+            name : String::from("capture.ps1"),
+            args : vec![String::from("-Uri")],
+        },
+        shell : ProtocolShellConfig {
+            name : String::from("pwsh"),
+            args : Vec::new(),
+        },
+        ..ProtocolConfig::default()
+    });
+    config
+}
+
+#[test]
+fn explain_matched_protocol() {
+    let config = config_with_protocol();
+    let explanation = explain("snip-proto://capture?template=c", &config);
+    assert_eq!(Some(String::from("snip-proto")), explanation.protocol);
+    assert!(explanation.protocols[0].matched);
+    assert_eq!(Some(String::from("pwsh")), explanation.shell);
+    assert_eq!(
+        vec!["pwsh", "-noProfile", "-noLogo", "-File", "capture.ps1", "-Uri", "\"snip-proto://capture?template=c\""],
+        explanation.argv
+    );
+    assert!(explanation.error.is_none());
+}
+
+#[test]
+fn explain_unconfigured_protocol() {
+    let config = config_with_protocol();
+    let explanation = explain("other://capture", &config);
+    assert!(!explanation.protocols[0].matched);
+    assert!(explanation.argv.is_empty());
+    assert!(explanation.error.is_some());
+    assert!(!explanation.is_ok());
+}
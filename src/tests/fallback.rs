@@ -0,0 +1,100 @@
+#[cfg(test)]
+use std::path::Path;
+
+#[cfg(test)]
+use crate::config::{AppendFileConfig, Config, FallbackAction, HandlerKind, ProtocolConfig, RouteConfig};
+#[cfg(test)]
+use crate::explain::explain;
+#[cfg(test)]
+use crate::runner::run_command;
+
+#[cfg(test)]
+fn append_to(path : &Path, template : &str) -> AppendFileConfig {
+    AppendFileConfig {
+        path : path.display().to_string(),
+        template : String::from(template),
+    }
+}
+
+#[test]
+fn catch_all_handles_unknown_schemes() {
+    let dir = tempfile::tempdir().unwrap();
+    let inbox = dir.path().join("inbox.md");
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        name : String::from("*"),
+        kind : HandlerKind::AppendFile,
+        append_file : Some(append_to(&inbox, "{scheme}")),
+        ..ProtocolConfig::default()
+    });
+
+    assert!(run_command(String::from("unknown://capture"), &config).success());
+    assert_eq!("unknown\n", std::fs::read_to_string(inbox).unwrap());
+    assert!(explain("unknown://capture", &config).protocols[0].matched);
+}
+
+#[test]
+fn routes_handle_subcommands() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        name : String::from("snip-proto"),
+        kind : HandlerKind::AppendFile,
+        append_file : Some(append_to(&dir.path().join("inbox.md"), "{subcommand}")),
+        routes : vec![
+            RouteConfig {
+                subcommand : String::from("todo"),
+                append_file : Some(append_to(&dir.path().join("todo.md"), "- [ ] {title}")),
+                ..RouteConfig::default()
+            },
+            RouteConfig {
+                subcommand : String::from("*"),
+                append_file : Some(append_to(&dir.path().join("other.md"), "{subcommand}")),
+                ..RouteConfig::default()
+            },
+        ],
+        ..ProtocolConfig::default()
+    });
+
+    let invocation = run_command(String::from("snip-proto://todo?title=Call"), &config);
+    assert_eq!(Some(String::from("todo")), invocation.route);
+    let invocation = run_command(String::from("snip-proto://unknown"), &config);
+    assert_eq!(Some(String::from("*")), invocation.route);
+    assert_eq!("- [ ] Call\n", std::fs::read_to_string(dir.path().join("todo.md")).unwrap());
+    assert_eq!("unknown\n", std::fs::read_to_string(dir.path().join("other.md")).unwrap());
+    assert!(!dir.path().join("inbox.md").exists());
+    assert_eq!(Some(String::from("todo")), explain("snip-proto://todo", &config).route);
+}
+
+#[test]
+fn fallback_exits_with_the_configured_code() {
+    let mut config = Config::new();
+    assert!(run_command(String::from("unknown://capture"), &config).error().is_some());
+
+    config.fallback.action = FallbackAction::Exit;
+    config.fallback.exit_code = 3;
+    let invocation = run_command(String::from("unknown://capture"), &config);
+    assert_eq!(Some(3), invocation.exit_code());
+
+    config.fallback.exit_code = 0;
+    assert!(run_command(String::from("unknown://capture"), &config).success());
+    assert!(explain("unknown://capture", &config).is_ok());
+}
+
+#[test]
+fn fallback_forwards_to_another_protocol() {
+    let dir = tempfile::tempdir().unwrap();
+    let inbox = dir.path().join("inbox.md");
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        name : String::from("snip-proto"),
+        kind : HandlerKind::AppendFile,
+        append_file : Some(append_to(&inbox, "{title}")),
+        ..ProtocolConfig::default()
+    });
+    config.fallback.action = FallbackAction::Forward;
+    config.fallback.uri = Some(String::from("snip-proto://capture?title={scheme}"));
+
+    assert!(run_command(String::from("unknown://capture"), &config).success());
+    assert_eq!("unknown\n", std::fs::read_to_string(inbox).unwrap());
+}
@@ -0,0 +1,170 @@
+#[cfg(test)]
+use crate::config::{AppendFileConfig, Config, ExecConfig, ForwardConfig, HandlerKind, ProtocolConfig, UriParser};
+#[cfg(test)]
+use crate::runner::{build_command, run_command};
+
+#[cfg(test)]
+fn protocol(name : &str, kind : HandlerKind) -> ProtocolConfig {
+    ProtocolConfig {
+        name : String::from(name),
+        kind,
+        ..ProtocolConfig::default()
+    }
+}
+
+#[test]
+fn exec_passes_templated_args() {
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        exec : Some(ExecConfig {
+            program : String::from("xdg-open"),
+            args : vec![String::from("--title={title}"), String::from("{subcommand}")],
+        }),
+        ..protocol("snip-proto", HandlerKind::Exec)
+    });
+
+    let command = build_command(String::from("snip-proto://capture?title=Get+started"), &config).unwrap();
+    assert_eq!("xdg-open", command.get_program());
+    let args : Vec<_> = command.get_args().collect();
+    assert_eq!(vec!["--title=Get started", "capture"], args);
+}
+
+#[test]
+fn exec_templates_the_fields_of_a_scheme() {
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        parser : UriParser::Ssh,
+        exec : Some(ExecConfig {
+            program : String::from("ssh"),
+            args : vec![String::from("-p"), String::from("{port|default:22}"), String::from("{user}@{host}")],
+        }),
+        ..protocol("ssh", HandlerKind::Exec)
+    });
+
+    let command = build_command(String::from("ssh://me@example.com"), &config).unwrap();
+    let args : Vec<_> = command.get_args().collect();
+    assert_eq!(vec!["-p", "22", "me@example.com"], args);
+}
+
+#[test]
+fn append_file_writes_a_line() {
+    let dir = tempfile::tempdir().unwrap();
+    let inbox = dir.path().join("notes").join("inbox.md");
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        append_file : Some(AppendFileConfig {
+            path : inbox.display().to_string(),
+            template : String::from("- [{title}]({url})"),
+        }),
+        ..protocol("snip-proto", HandlerKind::AppendFile)
+    });
+
+    for title in ["One", "Two"] {
+        let uri = format!("snip-proto://capture?title={title}&url=https://example.com");
+        assert!(run_command(uri, &config).success());
+    }
+    let content = std::fs::read_to_string(inbox).unwrap();
+    assert_eq!("- [One](https://example.com)\n- [Two](https://example.com)\n", content);
+}
+
+#[test]
+fn org_protocol_values_are_named() {
+    let dir = tempfile::tempdir().unwrap();
+    let inbox = dir.path().join("inbox.org");
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        parser : UriParser::OrgProtocol,
+        append_file : Some(AppendFileConfig {
+            path : inbox.display().to_string(),
+            template : String::from("* [[{url}][{title}]] ({template})"),
+        }),
+        ..protocol("org-protocol", HandlerKind::AppendFile)
+    });
+
+    let old = "org-protocol://capture:/w/https%253A%252F%252Fexample.com/Example/";
+    let new = "org-protocol://capture?template=w&url=https%3A%2F%2Fexample.com&title=Example";
+    for uri in [old, new] {
+        assert!(run_command(String::from(uri), &config).success());
+    }
+    let content = std::fs::read_to_string(inbox).unwrap();
+    assert_eq!("* [[https://example.com][Example]] (w)\n".repeat(2), content);
+}
+
+#[test]
+fn forward_dispatches_the_new_uri() {
+    let dir = tempfile::tempdir().unwrap();
+    let inbox = dir.path().join("inbox.md");
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        forward : Some(ForwardConfig { uri : String::from("snip-proto://capture?{query}") }),
+        ..protocol("old-snip", HandlerKind::Forward)
+    });
+    config.protocols.push(ProtocolConfig {
+        append_file : Some(AppendFileConfig {
+            path : inbox.display().to_string(),
+            template : String::from("{title}"),
+        }),
+        ..protocol("snip-proto", HandlerKind::AppendFile)
+    });
+
+    let invocation = run_command(String::from("old-snip://save?title=Forwarded"), &config);
+    assert!(invocation.success());
+    assert_eq!(Some(String::from("old-snip")), invocation.protocol);
+    assert_eq!(vec![String::from("snip-proto://capture?title=Forwarded")], invocation.forwards);
+    assert_eq!("Forwarded\n", std::fs::read_to_string(inbox).unwrap());
+}
+
+#[test]
+fn forward_detects_loops() {
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        forward : Some(ForwardConfig { uri : String::from("pong://{subcommand}") }),
+        ..protocol("ping", HandlerKind::Forward)
+    });
+    config.protocols.push(ProtocolConfig {
+        forward : Some(ForwardConfig { uri : String::from("ping://{subcommand}") }),
+        ..protocol("pong", HandlerKind::Forward)
+    });
+
+    let invocation = run_command(String::from("ping://ball"), &config);
+    assert!(!invocation.success());
+    assert!(invocation.error().unwrap().contains("loop"));
+}
+
+#[test]
+fn missing_handler_section_is_an_error() {
+    let mut config = Config::new();
+    config.protocols.push(protocol("snip-proto", HandlerKind::Http));
+
+    let invocation = run_command(String::from("snip-proto://capture"), &config);
+    assert!(invocation.error().unwrap().contains("has no 'http' section"));
+}
+
+#[test]
+fn append_file_entries_do_not_interleave() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        append_file : Some(AppendFileConfig {
+            path : dir.path().join("{subcommand}.md").display().to_string(),
+            template : String::from("## {title}\n\n{body}\n"),
+        }),
+        ..protocol("snip-proto", HandlerKind::AppendFile)
+    });
+
+    std::thread::scope(|scope| {
+        for n in 0..8 {
+            let config = &config;
+            scope.spawn(move || {
+                let uri = format!("snip-proto://journal?title=Entry+{n}&body={}", "x".repeat(4096));
+                assert!(run_command(uri, config).success());
+            });
+        }
+    });
+    let content = std::fs::read_to_string(dir.path().join("journal.md")).unwrap();
+    let entries : Vec<&str> = content.split("## ").skip(1).collect();
+    assert_eq!(8, entries.len());
+    for entry in entries {
+        assert!(entry.ends_with(&format!("\n\n{}\n", "x".repeat(4096))));
+    }
+}
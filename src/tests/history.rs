@@ -0,0 +1,83 @@
+#[cfg(test)]
+use crate::config::Config;
+#[cfg(test)]
+use crate::history::{find, load, record, HistoryEntry};
+#[cfg(test)]
+use crate::runner::run_command;
+
+#[cfg(test)]
+fn history_config(dir : &tempfile::TempDir, max_entries : usize) -> Config {
+    let mut config = Config::new();
+    config.history.path = Some(dir.path().join("history.jsonl").display().to_string());
+    config.history.max_entries = max_entries;
+    config
+}
+
+#[test]
+fn keeps_the_last_entries() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = history_config(&dir, 2);
+    for uri in ["unknown://one", "unknown://two", "unknown://three"] {
+        let invocation = run_command(String::from(uri), &config);
+        record(&HistoryEntry::new(&invocation), &config).unwrap();
+    }
+
+    let entries = load(&config).unwrap();
+    assert_eq!(2, entries.len());
+    assert_eq!("unknown://two", entries[0].uri);
+    assert_eq!("unknown://three", entries[1].uri);
+    assert!(!entries[1].success);
+}
+
+#[test]
+fn prunes_once_past_the_threshold() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = history_config(&dir, 4);
+    for i in 0..5 {
+        let invocation = run_command(format!("unknown://{i}"), &config);
+        record(&HistoryEntry::new(&invocation), &config).unwrap();
+    }
+    assert_eq!(5, load(&config).unwrap().len());
+
+    let invocation = run_command(String::from("unknown://5"), &config);
+    record(&HistoryEntry::new(&invocation), &config).unwrap();
+    let entries = load(&config).unwrap();
+    assert_eq!(4, entries.len());
+    assert_eq!("unknown://2", entries[0].uri);
+    assert_eq!(2, std::fs::read_dir(dir.path()).unwrap().count());
+}
+
+#[test]
+fn redacts_the_uris_unless_raw() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = history_config(&dir, 10);
+    let invocation = run_command(String::from("unknown://open?token=abc"), &config);
+    record(&HistoryEntry::new(&invocation), &config).unwrap();
+    config.history.raw_uris = true;
+    record(&HistoryEntry::new(&invocation), &config).unwrap();
+
+    let entries = load(&config).unwrap();
+    assert_eq!("unknown://open?token=REDACTED", entries[0].uri);
+    assert!(entries[0].uri_redacted);
+    assert_eq!("unknown://open?token=abc", entries[1].uri);
+    assert!(!entries[1].uri_redacted);
+}
+
+#[test]
+fn finds_entries_by_prefix() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = history_config(&dir, 10);
+    let invocation = run_command(String::from("unknown://one"), &config);
+    record(&HistoryEntry::new(&invocation), &config).unwrap();
+
+    let entries = load(&config).unwrap();
+    assert_eq!("unknown://one", find(&entries, &invocation.id[..8]).unwrap().uri);
+    assert!(find(&entries, "not-an-id").is_err());
+}
+
+#[test]
+fn missing_history_is_empty() {
+    let dir = tempfile::tempdir().unwrap();
+    let config = history_config(&dir, 10);
+    assert!(load(&config).unwrap().is_empty());
+}
@@ -0,0 +1,140 @@
+#[cfg(test)]
+use std::collections::BTreeMap;
+#[cfg(test)]
+use std::io::{BufRead, BufReader, Read, Write};
+#[cfg(test)]
+use std::net::TcpListener;
+#[cfg(test)]
+use std::thread::{self, JoinHandle};
+
+#[cfg(test)]
+use crate::config::{Config, HandlerKind, HttpBody, HttpConfig, HttpMethod, ProtocolConfig, RetryConfig};
+#[cfg(test)]
+use crate::runner::run_command;
+
+/// Starts a stand-in for the capture service, answering each request with the
+/// next status and returning the requests it received
+#[cfg(test)]
+fn serve(statuses : Vec<u16>) -> (String, JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let mut requests = Vec::new();
+        for status in statuses {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        length = value.trim().parse().unwrap();
+                    }
+                }
+                request.push_str(&line);
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0; length];
+            reader.read_exact(&mut body).unwrap();
+            request.push_str(&String::from_utf8(body).unwrap());
+            write!(reader.get_mut(), "HTTP/1.1 {status} Status\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").unwrap();
+            requests.push(request);
+        }
+        requests
+    });
+    (url, server)
+}
+
+#[cfg(test)]
+fn http_config(http : HttpConfig) -> Config {
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        name : String::from("snip-proto"),
+        kind : HandlerKind::Http,
+        http : Some(http),
+        ..ProtocolConfig::default()
+    });
+    config
+}
+
+#[test]
+fn posts_the_payload_as_json() {
+    let (url, server) = serve(vec![201]);
+    let config = http_config(HttpConfig { url : format!("{url}/{{subcommand}}"), ..HttpConfig::default() });
+
+    let invocation = run_command(String::from("snip-proto://capture?title=Get+started"), &config);
+    assert!(invocation.success());
+    assert_eq!(Some(0), invocation.exit_code());
+    let request = &server.join().unwrap()[0];
+    assert!(request.starts_with("POST /capture HTTP/1.1"));
+    assert!(request.contains("\"query\":{\"title\":\"Get started\"}"));
+}
+
+#[test]
+fn sends_headers_and_form_fields() {
+    let (url, server) = serve(vec![200]);
+    let config = http_config(HttpConfig {
+        url,
+        method : HttpMethod::Put,
+        headers : BTreeMap::from([(String::from("X-Title"), String::from("{title}"))]),
+        body : Some(HttpBody::Form),
+        fields : BTreeMap::from([(String::from("note"), String::from("{title|default:none} from {scheme}"))]),
+        ..HttpConfig::default()
+    });
+
+    assert!(run_command(String::from("snip-proto://capture?title=Hello"), &config).success());
+    let request = &server.join().unwrap()[0];
+    assert!(request.starts_with("PUT / HTTP/1.1"));
+    assert!(request.contains("X-Title: Hello\r\n"));
+    assert!(request.ends_with("note=Hello+from+snip-proto"));
+}
+
+#[test]
+fn sends_the_fields_of_bodiless_methods_in_the_query() {
+    let (url, server) = serve(vec![200, 200]);
+    let mut config = http_config(HttpConfig { url, method : HttpMethod::Get, ..HttpConfig::default() });
+
+    assert!(run_command(String::from("snip-proto://capture?title=Get+started"), &config).success());
+    config.protocols[0].http.as_mut().unwrap().body = Some(HttpBody::None);
+    assert!(run_command(String::from("snip-proto://capture?title=Get+started"), &config).success());
+    let requests = server.join().unwrap();
+    assert!(requests[0].starts_with("GET /?title=Get+started HTTP/1.1"));
+    assert!(!requests[0].to_ascii_lowercase().contains("content-type"));
+    assert!(requests[1].starts_with("GET / HTTP/1.1"));
+}
+
+#[test]
+fn retries_server_errors() {
+    let (url, server) = serve(vec![503, 503, 200]);
+    let config = http_config(HttpConfig { url, retries : 2, retry_delay : 10, ..HttpConfig::default() });
+
+    assert!(run_command(String::from("snip-proto://capture"), &config).success());
+    assert_eq!(3, server.join().unwrap().len());
+}
+
+#[test]
+fn maps_the_status_to_the_exit_code() {
+    let (url, server) = serve(vec![404]);
+    let config = http_config(HttpConfig { url, retries : 2, ..HttpConfig::default() });
+
+    let invocation = run_command(String::from("snip-proto://capture"), &config);
+    assert!(!invocation.success());
+    assert_eq!(Some(4), invocation.exit_code());
+    assert_eq!(1, server.join().unwrap().len());
+}
+
+#[test]
+fn is_only_retried_by_the_handler() {
+    let (url, server) = serve(vec![503, 503]);
+    let mut config = http_config(HttpConfig { url, retries : 1, retry_delay : 10, ..HttpConfig::default() });
+    config.protocols[0].retry = RetryConfig { max_attempts : 3, backoff : 1, ..RetryConfig::default() };
+
+    let invocation = run_command(String::from("snip-proto://capture"), &config);
+    assert!(!invocation.success());
+    assert_eq!(1, invocation.attempts);
+    assert_eq!(2, server.join().unwrap().len());
+}
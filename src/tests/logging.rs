@@ -0,0 +1,67 @@
+#[cfg(test)]
+use std::fs;
+#[cfg(test)]
+use std::io::Write;
+#[cfg(test)]
+use crate::config::{LoggingConfig, TerminalLogging};
+#[cfg(test)]
+use crate::error::ProtoHandlerError;
+#[cfg(test)]
+use crate::logging::{open_log_file, rotate};
+#[cfg(test)]
+use simplelog::LevelFilter;
+
+#[test]
+fn rotate_keeps_max_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("test.log");
+    for content in ["first", "second", "third"] {
+        fs::write(&log, content).unwrap();
+        rotate(&log, 1, 2).unwrap();
+    }
+    assert!(!log.exists());
+    assert_eq!("third", fs::read_to_string(dir.path().join("test.log.1")).unwrap());
+    assert_eq!("second", fs::read_to_string(dir.path().join("test.log.2")).unwrap());
+    assert!(!dir.path().join("test.log.3").exists());
+}
+
+#[test]
+fn rotate_ignores_small_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("test.log");
+    fs::write(&log, "small").unwrap();
+    rotate(&log, 1024, 2).unwrap();
+    assert!(log.exists());
+    assert!(!dir.path().join("test.log.1").exists());
+}
+
+#[test]
+fn rotates_between_lines_while_writing() {
+    let dir = tempfile::tempdir().unwrap();
+    let log = dir.path().join("test.log");
+    let logging = LoggingConfig { max_size : Some(10), max_files : 1, ..LoggingConfig::default() };
+    let mut file = open_log_file(&log, &logging).unwrap();
+    file.write_all(b"first line\n").unwrap();
+    file.write_all(b"second").unwrap();
+    file.write_all(b" line\n").unwrap();
+
+    assert_eq!("first line\n", fs::read_to_string(dir.path().join("test.log.1")).unwrap());
+    assert_eq!("second line\n", fs::read_to_string(&log).unwrap());
+}
+
+#[test]
+fn invalid_time_formats_are_errors() {
+    let logging = LoggingConfig { time_format : Some(String::from("[hour")), ..LoggingConfig::default() };
+    assert!(matches!(simplelog::Config::try_from(&logging), Err(ProtoHandlerError::TimeFormatError { .. })));
+    let logging = LoggingConfig { time_format : Some(String::from("[hour]:[minute]")), ..LoggingConfig::default() };
+    assert!(simplelog::Config::try_from(&logging).is_ok());
+}
+
+#[test]
+fn levels_default_to_level() {
+    let logging : LoggingConfig = serde_yml::from_str("path: test.log\nlevel: warn\nfile_level: debug\n").unwrap();
+    assert_eq!(LevelFilter::Warn, logging.terminal_level());
+    assert_eq!(LevelFilter::Debug, logging.file_level());
+    assert_eq!(TerminalLogging::Auto, logging.terminal);
+    assert!(logging.append);
+}
@@ -0,0 +1,68 @@
+#[cfg(test)]
+use std::io::Cursor;
+#[cfg(test)]
+use std::path::Path;
+
+#[cfg(test)]
+use serde_json::{json, Value};
+
+#[cfg(test)]
+use crate::native::{read_message, serve, write_message, Browser, Message};
+
+#[cfg(test)]
+fn frames(messages : &[Value]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for message in messages {
+        write_message(&mut out, message).unwrap();
+    }
+    out
+}
+
+#[test]
+fn messages_are_length_prefixed() {
+    let bytes = frames(&[json!("snip-proto://capture")]);
+    assert_eq!(22u32.to_ne_bytes(), bytes[..4]);
+    let message = read_message(&mut Cursor::new(bytes)).unwrap();
+    assert_eq!(Some(Message::Uri(String::from("snip-proto://capture"))), message);
+    assert_eq!(None, read_message(&mut Cursor::new(Vec::new())).unwrap());
+}
+
+#[test]
+fn every_message_is_answered() {
+    let input = frames(&[
+        json!("snip-proto://one"),
+        json!({ "uri" : "snip-proto://two" }),
+        json!({ "scheme" : "snip-proto", "subcommand" : "capture", "query" : { "title" : "Get started" } }),
+        json!(42),
+    ]);
+    let mut output = Vec::new();
+    serve(&mut Cursor::new(input), &mut output, |uri| json!({ "uri" : uri, "success" : true })).unwrap();
+
+    let mut output = Cursor::new(output);
+    let mut replies = Vec::new();
+    while let Ok(Some(Message::Request { uri })) = read_message(&mut output) {
+        replies.push(uri);
+    }
+    assert_eq!(vec!["snip-proto://one", "snip-proto://two", "snip-proto://capture?title=Get%20started"], replies);
+}
+
+#[test]
+fn invalid_messages_are_answered_with_an_error() {
+    let mut output = Vec::new();
+    serve(&mut Cursor::new(frames(&[json!(42)])), &mut output, |uri| uri).unwrap();
+    let reply : Value = serde_json::from_slice(&output[4..]).unwrap();
+    assert_eq!(json!(false), reply["success"]);
+}
+
+#[test]
+fn manifests_allow_the_extensions() {
+    let path = Path::new("/home/me/.config/protohandler/native-host");
+    let chrome = Browser::Chrome.manifest(path, &[String::from("abcdef")]);
+    assert_eq!(json!("protohandlers"), chrome["name"]);
+    assert_eq!(json!("stdio"), chrome["type"]);
+    assert_eq!(json!(["chrome-extension://abcdef/"]), chrome["allowed_origins"]);
+
+    let firefox = Browser::Firefox.manifest(path, &[String::from("capture@example.org")]);
+    assert_eq!(json!(["capture@example.org"]), firefox["allowed_extensions"]);
+    assert_eq!(json!(path.display().to_string()), firefox["path"]);
+}
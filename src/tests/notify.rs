@@ -0,0 +1,115 @@
+#[cfg(test)]
+use crate::config::{
+    AppendFileConfig, Config, FallbackAction, ForwardConfig, HandlerKind, NotificationBackend, NotifyConfig, NotifyOn, ProtocolConfig,
+    UriParser,
+};
+#[cfg(test)]
+use crate::notify::notification;
+#[cfg(test)]
+use crate::runner::run_command;
+
+#[cfg(test)]
+fn capture(notify : NotifyConfig) -> ProtocolConfig {
+    ProtocolConfig {
+        name : String::from("snip-proto"),
+        kind : HandlerKind::Forward,
+        forward : Some(ForwardConfig { uri : String::from("missing-proto://{subcommand}") }),
+        notify,
+        ..ProtocolConfig::default()
+    }
+}
+
+#[test]
+fn notifications_follow_the_outcome() {
+    let mut config = Config::new();
+    config.protocols.push(capture(NotifyConfig { on : NotifyOn::Error, ..NotifyConfig::default() }));
+    let invocation = run_command(String::from("snip-proto://capture?title=x"), &config);
+
+    let on_error = notification(&invocation, &config.protocols[0].notify, &config).unwrap();
+    assert_eq!("snip-proto failed", on_error.title);
+    assert!(on_error.body.starts_with("snip-proto://capture?title=x\n"));
+    assert!(on_error.body.contains("missing-proto"));
+    assert_eq!("critical", on_error.urgency());
+
+    let on_success = NotifyConfig { on : NotifyOn::Success, ..NotifyConfig::default() };
+    assert!(notification(&invocation, &on_success, &config).is_none());
+    assert!(notification(&invocation, &NotifyConfig::default(), &config).is_none());
+
+    let custom = NotifyConfig {
+        on : NotifyOn::Always,
+        title : Some(String::from("{title}: {status} ({exit_code})")),
+        body : Some(String::from("{route|default:no route}")),
+    };
+    let always = notification(&invocation, &custom, &config).unwrap();
+    assert_eq!("x: failed ()", always.title);
+    assert_eq!("no route", always.body);
+}
+
+#[test]
+fn notifications_use_the_parser_of_the_protocol() {
+    let mut config = Config::new();
+    let notify = NotifyConfig { on : NotifyOn::Always, title : Some(String::from("{title}")), ..NotifyConfig::default() };
+    config.protocols.push(ProtocolConfig {
+        name : String::from("org-protocol"),
+        parser : UriParser::OrgProtocol,
+        ..capture(notify.clone())
+    });
+    let invocation = run_command(String::from("org-protocol://store-link:/https%3A%2F%2Fexample.com/Example"), &config);
+
+    assert_eq!("Example", notification(&invocation, &notify, &config).unwrap().title);
+}
+
+#[cfg(unix)]
+#[test]
+fn notifications_run_the_configured_command() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("notification");
+    let mut config = Config::new();
+    config.notifications.backend = NotificationBackend::Command;
+    config.notifications.program = String::from("sh");
+    config.notifications.args = vec![
+        String::from("-c"),
+        format!("printf '%s|%s|%s' \"$0\" \"$1\" \"$2\" > '{}'", out.display()),
+        String::from("{urgency}"),
+        String::from("{title}"),
+        String::from("{body}"),
+    ];
+    config.protocols.push(ProtocolConfig {
+        kind : HandlerKind::AppendFile,
+        append_file : Some(AppendFileConfig {
+            path : dir.path().join("inbox.md").display().to_string(),
+            template : String::from("{title}"),
+        }),
+        ..capture(NotifyConfig {
+            on : NotifyOn::Always,
+            body : Some(String::from("Captured {title}")),
+            ..NotifyConfig::default()
+        })
+    });
+
+    assert!(run_command(String::from("snip-proto://capture?title=x"), &config).success());
+    assert_eq!("normal|snip-proto succeeded|Captured x", std::fs::read_to_string(out).unwrap());
+}
+
+#[cfg(unix)]
+#[test]
+fn fallback_notifications_use_the_configured_backend() {
+    let dir = tempfile::tempdir().unwrap();
+    let out = dir.path().join("notification");
+    let mut config = Config::new();
+    config.fallback.action = FallbackAction::Notify;
+    config.notifications.backend = NotificationBackend::Command;
+    config.notifications.program = String::from("sh");
+    config.notifications.args = vec![
+        String::from("-c"),
+        format!("printf '%s|%s' \"$0\" \"$1\" > '{}'", out.display()),
+        String::from("{urgency}"),
+        String::from("{body}"),
+    ];
+
+    assert!(!run_command(String::from("unknown://open?token=abc"), &config).success());
+    assert_eq!(
+        "critical|No protocol configured for 'unknown://open?token=REDACTED'",
+        std::fs::read_to_string(out).unwrap()
+    );
+}
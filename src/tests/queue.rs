@@ -0,0 +1,117 @@
+#[cfg(test)]
+use std::path::Path;
+#[cfg(test)]
+use std::time::Duration;
+
+#[cfg(test)]
+use crate::config::{ConcurrencyConfig, ConcurrencyMode, Config, ExecConfig, HandlerKind, ProtocolConfig, RetryConfig};
+#[cfg(test)]
+use crate::queue;
+#[cfg(test)]
+use crate::runner::run_command;
+
+#[cfg(test)]
+fn failing(dir : &Path, script : &str, retry : RetryConfig) -> Config {
+    let mut config = Config::new();
+    config.state_dir = Some(dir.display().to_string());
+    config.protocols.push(ProtocolConfig {
+        name : String::from("snip-proto"),
+        kind : HandlerKind::Exec,
+        exec : Some(ExecConfig {
+            program : String::from("sh"),
+            args : vec![String::from("-c"), String::from(script)],
+        }),
+        retry,
+        ..ProtocolConfig::default()
+    });
+    config
+}
+
+#[test]
+fn backoff_grows_with_every_retry() {
+    let retry = RetryConfig { backoff : 100, backoff_factor : 3, ..RetryConfig::default() };
+    assert_eq!(Duration::from_millis(100), retry.delay(1));
+    assert_eq!(Duration::from_millis(900), retry.delay(3));
+}
+
+#[cfg(unix)]
+#[test]
+fn failed_handlers_are_retried() {
+    let dir = tempfile::tempdir().unwrap();
+    let flag = dir.path().join("flag");
+    let script = format!("if [ -e '{0}' ]; then exit 0; else touch '{0}'; exit 3; fi", flag.display());
+    let retry = RetryConfig { max_attempts : 3, backoff : 1, dead_letter : true, ..RetryConfig::default() };
+    let config = failing(dir.path(), &script, retry);
+
+    let invocation = run_command(String::from("snip-proto://capture"), &config);
+    assert!(invocation.success());
+    assert_eq!(2, invocation.attempts);
+    assert!(queue::load(&config).unwrap().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn dropping_duplicates_does_not_reject_retries() {
+    let dir = tempfile::tempdir().unwrap();
+    let flag = dir.path().join("flag");
+    let script = format!("if [ -e '{0}' ]; then exit 0; else touch '{0}'; exit 3; fi", flag.display());
+    let retry = RetryConfig { max_attempts : 3, backoff : 1, dead_letter : true, ..RetryConfig::default() };
+    let mut config = failing(dir.path(), &script, retry);
+    config.runtime_dir = Some(dir.path().display().to_string());
+    config.protocols[0].concurrency = ConcurrencyConfig { mode : ConcurrencyMode::DropDuplicates, window : 60, ..ConcurrencyConfig::default() };
+
+    let invocation = run_command(String::from("snip-proto://capture"), &config);
+    assert!(invocation.success());
+    assert_eq!(2, invocation.attempts);
+    assert!(queue::load(&config).unwrap().is_empty());
+    assert!(!run_command(String::from("snip-proto://capture"), &config).success());
+}
+
+#[cfg(unix)]
+#[test]
+fn exhausted_uris_are_queued() {
+    let dir = tempfile::tempdir().unwrap();
+    let retry = RetryConfig { max_attempts : 2, backoff : 1, dead_letter : true, ..RetryConfig::default() };
+    let config = failing(dir.path(), "exit 3", retry);
+
+    let invocation = run_command(String::from("snip-proto://capture?title=x"), &config);
+    assert_eq!(Some(3), invocation.exit_code());
+    assert_eq!(2, invocation.attempts);
+    assert!(invocation.decisions.iter().any(|d| d.name == "dead-letter"));
+
+    let letters = queue::load(&config).unwrap();
+    assert_eq!(1, letters.len());
+    assert_eq!("snip-proto://capture?title=x", letters[0].uri);
+    assert_eq!(2, letters[0].attempts);
+    let letter = queue::find(&letters, &invocation.id[..8]).unwrap();
+    assert!(queue::find(&letters, "nope").is_err());
+
+    queue::remove(letter, &config).unwrap();
+    assert!(queue::load(&config).unwrap().is_empty());
+}
+
+#[test]
+fn unconfigured_protocols_are_not_retried() {
+    let dir = tempfile::tempdir().unwrap();
+    let retry = RetryConfig { max_attempts : 3, backoff : 1, dead_letter : true, ..RetryConfig::default() };
+    let config = failing(dir.path(), "exit 3", retry);
+
+    let invocation = run_command(String::from("unknown-proto://capture"), &config);
+    assert_eq!(1, invocation.attempts);
+    assert!(!invocation.success());
+    assert!(queue::load(&config).unwrap().is_empty());
+}
+
+#[cfg(unix)]
+#[test]
+fn rejected_uris_are_not_retried() {
+    let dir = tempfile::tempdir().unwrap();
+    let retry = RetryConfig { max_attempts : 3, backoff : 1, dead_letter : true, ..RetryConfig::default() };
+    let mut config = failing(dir.path(), "exit 0", retry);
+    config.protocols[0].concurrency = ConcurrencyConfig { mode : ConcurrencyMode::Max, max : 0, exit_code : 9, ..ConcurrencyConfig::default() };
+
+    let invocation = run_command(String::from("snip-proto://capture"), &config);
+    assert_eq!(Some(9), invocation.exit_code());
+    assert_eq!(1, invocation.attempts);
+    assert!(queue::load(&config).unwrap().is_empty());
+}
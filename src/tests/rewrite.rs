@@ -0,0 +1,98 @@
+#[cfg(test)]
+use std::collections::BTreeMap;
+
+#[cfg(test)]
+use crate::config::{AppendFileConfig, Config, HandlerKind, ProtocolConfig, RewriteRule, UriParser};
+#[cfg(test)]
+use crate::explain::explain;
+#[cfg(test)]
+use crate::rewrite::apply;
+#[cfg(test)]
+use crate::runner::run_command;
+
+#[test]
+fn renames_params_and_sets_defaults() {
+    let rules = vec![RewriteRule {
+        rename : BTreeMap::from([(String::from("u"), String::from("url"))]),
+        defaults : BTreeMap::from([(String::from("template"), String::from("c")), (String::from("url"), String::from("none"))]),
+        ..RewriteRule::default()
+    }];
+    let uri = apply("snip-proto://capture?u=https%3A%2F%2Fexample.com", &rules, UriParser::Standard).unwrap();
+    assert_eq!("snip-proto://capture?url=https%3A%2F%2Fexample.com&template=c", uri);
+}
+
+#[test]
+fn changes_components_with_the_parser_of_the_protocol() {
+    let rules = vec![RewriteRule {
+        rename : BTreeMap::from([(String::from("title"), String::from("description"))]),
+        ..RewriteRule::default()
+    }];
+    let uri = apply("org-protocol://store-link:/https%3A%2F%2Fexample.com/Example", &rules, UriParser::OrgProtocol).unwrap();
+    assert_eq!("org-protocol://store-link?url=https%3A%2F%2Fexample.com&description=Example", uri);
+}
+
+#[test]
+fn replaces_matching_uris_only() {
+    let rules = vec![RewriteRule {
+        matches : Some(String::from("^snip-proto://clip/(.*)$")),
+        replace : Some(String::from("snip-proto://capture/$1")),
+        ..RewriteRule::default()
+    }];
+    assert_eq!("snip-proto://capture/a?b=c", apply("snip-proto://clip/a?b=c", &rules, UriParser::Standard).unwrap());
+    assert_eq!("snip-proto://other", apply("snip-proto://other", &rules, UriParser::Standard).unwrap());
+
+    let invalid = vec![RewriteRule { matches : Some(String::from("(")), ..RewriteRule::default() }];
+    assert!(apply("snip-proto://other", &invalid, UriParser::Standard).is_err());
+}
+
+#[cfg(test)]
+fn migrated_config(inbox : &std::path::Path) -> Config {
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        name : String::from("snip-proto"),
+        rewrite : vec![RewriteRule {
+            scheme : Some(String::from("snip")),
+            rename : BTreeMap::from([(String::from("t"), String::from("title"))]),
+            ..RewriteRule::default()
+        }],
+        ..ProtocolConfig::default()
+    });
+    config.protocols.push(ProtocolConfig {
+        name : String::from("snip"),
+        kind : HandlerKind::AppendFile,
+        append_file : Some(AppendFileConfig {
+            path : inbox.display().to_string(),
+            template : String::from("{title}"),
+        }),
+        ..ProtocolConfig::default()
+    });
+    config
+}
+
+#[test]
+fn forwards_to_the_new_scheme() {
+    let dir = tempfile::tempdir().unwrap();
+    let inbox = dir.path().join("inbox.md");
+    let config = migrated_config(&inbox);
+
+    let invocation = run_command(String::from("snip-proto://capture?t=Legacy"), &config);
+    assert!(invocation.success());
+    assert_eq!(vec![String::from("snip://capture?title=Legacy")], invocation.forwards);
+    assert_eq!("Legacy\n", std::fs::read_to_string(inbox).unwrap());
+
+    let explanation = explain("snip-proto://capture?t=Legacy", &config);
+    assert_eq!(vec![String::from("snip://capture?title=Legacy")], explanation.rewrites);
+    assert_eq!(Some(HandlerKind::AppendFile), explanation.kind);
+    assert_eq!(Some(String::from("snip-proto")), explanation.protocol);
+}
+
+#[test]
+fn detects_rewrite_loops() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = migrated_config(&dir.path().join("inbox.md"));
+    config.protocols[1].rewrite = vec![RewriteRule { scheme : Some(String::from("snip-proto")), ..RewriteRule::default() }];
+
+    let invocation = run_command(String::from("snip-proto://capture"), &config);
+    assert!(invocation.error().unwrap().contains("loop"));
+    assert!(explain("snip-proto://capture", &config).error.unwrap().contains("loop"));
+}
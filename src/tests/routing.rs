@@ -0,0 +1,172 @@
+#[cfg(test)]
+use crate::config::{AppendFileConfig, Config, HandlerKind, Predicate, ProtocolConfig, RouteConfig};
+#[cfg(test)]
+use crate::explain::{check, explain};
+#[cfg(test)]
+use crate::routing::{conditions, find, PathPattern};
+#[cfg(test)]
+use crate::runner::run_command;
+#[cfg(test)]
+use crate::uri::ParsedUri;
+
+#[cfg(test)]
+fn route(subcommand : &str, path : Option<&str>) -> RouteConfig {
+    RouteConfig {
+        subcommand : String::from(subcommand),
+        path : path.map(String::from),
+        ..RouteConfig::default()
+    }
+}
+
+#[cfg(test)]
+fn routed(routes : &[RouteConfig], uri : &str) -> Option<String> {
+    find(routes, &ParsedUri::parse(uri).unwrap()).map(|m| m.route.name())
+}
+
+#[test]
+fn patterns_capture_segments() {
+    let pattern = PathPattern::parse("/open/{notebook}/{page?}").unwrap();
+    let captures = pattern.captures("/open/projects").unwrap();
+    assert_eq!(("notebook", "projects"), (captures[0].name.as_str(), captures[0].value.as_str()));
+    assert_eq!("", captures[1].value);
+    assert_eq!("readme", pattern.captures("/open/projects/readme/").unwrap()[1].value);
+    assert!(pattern.captures("/open").is_none());
+    assert!(pattern.captures("/open/projects/readme/more").is_none());
+
+    let pattern = PathPattern::parse("/issue/{id:[0-9]+}").unwrap();
+    assert_eq!("42", pattern.captures("/issue/42").unwrap()[0].value);
+    assert!(pattern.captures("/issue/abc").is_none());
+
+    let pattern = PathPattern::parse("/open/{*path}").unwrap();
+    assert_eq!("", pattern.captures("/open").unwrap()[0].value);
+}
+
+#[test]
+fn invalid_patterns_are_errors() {
+    for pattern in ["/open/{*path}/more", "/open/{1st}", "/open/{a}/{a}", "/open/{id:[0-9}", "/open/x{id}"] {
+        assert!(PathPattern::parse(pattern).is_err(), "{pattern}");
+    }
+}
+
+#[test]
+fn the_most_specific_route_wins() {
+    let routes = vec![
+        route("*", None),
+        route("", Some("/open/{*path}")),
+        route("open", None),
+        route("", Some("/open/{notebook}/{*path}")),
+        route("", Some("/open/journal/{*path}")),
+        route("", Some("/open/{notebook}/{day:[0-9]{4}-[0-9]{2}-[0-9]{2}}")),
+    ];
+    assert_eq!(Some("/open/journal/{*path}"), routed(&routes, "notes://open/journal/today").as_deref());
+    assert_eq!(Some("/open/{notebook}/{day:[0-9]{4}-[0-9]{2}-[0-9]{2}}"), routed(&routes, "notes://open/work/2024-01-02").as_deref());
+    assert_eq!(Some("/open/{notebook}/{*path}"), routed(&routes, "notes://open/work/a/b.md").as_deref());
+    assert_eq!(Some("/open/{*path}"), routed(&routes, "notes://open").as_deref());
+    assert_eq!(Some("*"), routed(&routes, "notes://close/work").as_deref());
+    assert_eq!(Some("open"), routed(&[route("*", None), route("open", None)], "notes://open").as_deref());
+    assert_eq!(Some("open"), routed(&[route("", Some("/{a}/{b}")), route("open", None)], "notes://open/x").as_deref());
+}
+
+#[test]
+fn compiled_routes_match_like_the_others() {
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        name : String::from("notes"),
+        routes : vec![
+            RouteConfig { when : Some(when("{ param: id, regex: '^[0-9]+$' }")), ..route("", Some("/open/{page}")) },
+            route("", Some("/open/{page:(}")),
+        ],
+        ..ProtocolConfig::default()
+    });
+    config.compile_routes();
+
+    let routes = &config.protocols[0].routes;
+    assert!(routes.iter().all(|r| r.compiled.is_some()));
+    assert_eq!(Some("/open/{page} when id =~ /^[0-9]+$/"), routed(routes, "notes://open/a?id=12").as_deref());
+    assert_eq!(None, routed(routes, "notes://open/a?id=x"));
+}
+
+#[test]
+fn captures_are_available_to_templates() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut config = Config::new();
+    config.protocols.push(ProtocolConfig {
+        name : String::from("notes"),
+        kind : HandlerKind::AppendFile,
+        routes : vec![RouteConfig {
+            append_file : Some(AppendFileConfig {
+                path : dir.path().join("{notebook}.md").display().to_string(),
+                template : String::from("{path}#{fragment}"),
+            }),
+            ..route("", Some("/open/{notebook}/{*path}"))
+        }],
+        ..ProtocolConfig::default()
+    });
+
+    let uri = "notes://open/projects/foo/readme.md#L10";
+    let invocation = run_command(String::from(uri), &config);
+    assert!(invocation.success());
+    assert_eq!(Some(String::from("/open/{notebook}/{*path}")), invocation.route);
+    assert_eq!("foo/readme.md#L10\n", std::fs::read_to_string(dir.path().join("projects.md")).unwrap());
+
+    let explanation = explain(uri, &config);
+    let captures : Vec<(&str, &str)> = explanation.captures.iter().map(|c| (c.name.as_str(), c.value.as_str())).collect();
+    assert_eq!(vec![("notebook", "projects"), ("path", "foo/readme.md")], captures);
+
+    config.protocols[0].routes.push(route("", Some("/open/{*rest}/x")));
+    assert!(check(&config).iter().any(|c| c.name == "notes: route pattern" && !c.passed));
+}
+
+#[cfg(test)]
+fn when(yaml : &str) -> Predicate {
+    serde_yml::from_str(yaml).unwrap()
+}
+
+#[test]
+fn predicates_are_parsed_from_yaml() {
+    let predicate = when("or:\n  - { param: template, equals: c }\n  - and:\n      - { param: url, regex: 'github\\.com' }\n      - not: { exists: draft }\n");
+    assert_eq!(r#"(template == "c" or (url =~ /github\.com/ and not (exists draft)))"#, predicate.to_string());
+    assert!(serde_yml::from_str::<Predicate>("{ param: template, equals: c, regex: c }").is_err());
+}
+
+#[test]
+fn conditions_select_routes() {
+    let routes = vec![
+        route("capture", None),
+        RouteConfig { when : Some(when("{ param: template, equals: c }")), ..route("capture", None) },
+        RouteConfig { when : Some(when("{ param: url, regex: 'github\\.com/.+/issues' }")), ..route("", None) },
+        RouteConfig {
+            when : Some(when("and:\n  - { param: url, regex: 'github\\.com' }\n  - { exists: title }")),
+            ..route("", None)
+        },
+    ];
+    assert_eq!(Some(r#"capture when template == "c""#), routed(&routes, "snip://capture?template=c").as_deref());
+    assert_eq!(Some("capture"), routed(&routes, "snip://capture?template=d").as_deref());
+    let uri = "snip://save?url=https://github.com/a/b/issues";
+    assert_eq!(Some("when url =~ /github\\.com/.+/issues/"), routed(&routes, uri).as_deref());
+    assert_eq!(
+        Some("when (url =~ /github\\.com/ and exists title)"),
+        routed(&routes, &format!("{uri}&title=Bug")).as_deref()
+    );
+    assert_eq!(None, routed(&routes, "snip://save?title=Bug"));
+}
+
+#[test]
+fn conditions_are_explained() {
+    let route = RouteConfig {
+        when : Some(when("and:\n  - { param: template, equals: c }\n  - not: { exists: draft }")),
+        ..route("", None)
+    };
+    let parsed = ParsedUri::parse("snip://capture?template=c&draft=").unwrap();
+    let conditions = conditions(&route, &parsed);
+    let outcomes : Vec<(usize, &str, bool)> = conditions.iter().map(|c| (c.depth, c.predicate.as_str(), c.passed)).collect();
+    assert_eq!(
+        vec![
+            (0, r#"(template == "c" and not (exists draft))"#, false),
+            (1, r#"template == "c""#, true),
+            (1, "not (exists draft)", false),
+            (2, "exists draft", true),
+        ],
+        outcomes
+    );
+}
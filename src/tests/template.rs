@@ -0,0 +1,45 @@
+#[cfg(test)]
+use time::macros::datetime;
+
+#[cfg(test)]
+use crate::template::{render, Context};
+#[cfg(test)]
+use crate::uri::ParsedUri;
+
+#[cfg(test)]
+fn context<'a>(uri : &'a str, parsed : &'a ParsedUri) -> Context<'a> {
+    Context {
+        now : datetime!(2024-09-30 14:05:09 UTC),
+        ..Context::new(uri, parsed)
+    }
+}
+
+#[test]
+fn renders_components_and_params() {
+    let uri = "snip-proto://capture/web?title=Get+started#top";
+    let parsed = ParsedUri::parse(uri).unwrap();
+    let text = render("{scheme} {subcommand} {path} {fragment} {title} [{missing}]", &context(uri, &parsed));
+    assert_eq!("snip-proto capture /web top Get started []", text);
+}
+
+#[test]
+fn renders_timestamps() {
+    let uri = "snip-proto://capture";
+    let parsed = ParsedUri::parse(uri).unwrap();
+    let context = context(uri, &parsed);
+    assert_eq!("notes/2024-09-30.md", render("notes/{date}.md", &context));
+    assert_eq!("14:05", render("{time:%H:%M}", &context));
+    assert_eq!("2024-09-30T14:05:09Z", render("{now}", &context));
+    assert_eq!("Sep 2024", render("{date:%b %Y}", &context));
+}
+
+#[test]
+fn applies_filters() {
+    let uri = "snip-proto://capture?title=+Get+started+&url=https://example.com/a?b=c";
+    let parsed = ParsedUri::parse(uri).unwrap();
+    let context = context(uri, &parsed);
+    assert_eq!("[Get started]", render("[{title|trim}]", &context));
+    assert_eq!("https%3A%2F%2Fexample.com%2Fa%3Fb%3Dc", render("{url|urlencode}", &context));
+    assert_eq!("Untitled", render("{body|trim|default:Untitled}", &context));
+    assert_eq!("Get started", render("{title|trim|default:Untitled}", &context));
+}
@@ -0,0 +1,158 @@
+#[cfg(test)]
+use crate::config::UriParser;
+#[cfg(test)]
+use crate::uri::ParsedUri;
+
+#[test]
+fn parse_components() {
+    let uri = ParsedUri::parse("snip-proto://capture/notes/today?template=c&title=Get+started&url=https%3A%2F%2Fexample.com#top")
+        .unwrap();
+    assert_eq!("snip-proto", uri.scheme);
+    assert_eq!("capture", uri.subcommand);
+    assert_eq!("/notes/today", uri.path);
+    assert_eq!(Some("c"), uri.param("template"));
+    assert_eq!(Some("Get started"), uri.param("title"));
+    assert_eq!(Some("https://example.com"), uri.param("url"));
+    assert_eq!(Some(String::from("top")), uri.fragment);
+}
+
+#[test]
+fn parse_without_payload() {
+    let uri = ParsedUri::parse("snip-proto://capture").unwrap();
+    assert_eq!("capture", uri.subcommand);
+    assert!(uri.query.is_empty());
+    assert!(uri.fragment.is_none());
+}
+
+#[test]
+fn parse_rejects_missing_protocol() {
+    assert!(ParsedUri::parse("capture?template=c").is_err());
+}
+
+#[test]
+fn display_encodes_components() {
+    let uri = ParsedUri::parse("snip-proto://capture/notes/my%20day?title=Get+started&url=https%3A%2F%2Fexample.com#top").unwrap();
+    assert_eq!(
+        "snip-proto://capture/notes/my%20day?title=Get%20started&url=https%3A%2F%2Fexample.com#top",
+        uri.to_string()
+    );
+    assert_eq!(uri, ParsedUri::parse(&uri.to_string()).unwrap());
+}
+
+#[test]
+fn org_protocol_parses_the_old_form() {
+    let uri = ParsedUri::parse_as("org-protocol://capture:/w/https%3A%2F%2Fexample.com%2Fa/Example/Some%20text", UriParser::OrgProtocol)
+        .unwrap();
+    assert_eq!("capture", uri.subcommand);
+    assert_eq!("", uri.path);
+    assert_eq!(Some("w"), uri.param("template"));
+    assert_eq!(Some("https://example.com/a"), uri.param("url"));
+    assert_eq!(Some("Example"), uri.param("title"));
+    assert_eq!(Some("Some text"), uri.param("body"));
+
+    let uri = ParsedUri::parse_as("org-protocol://store-link://https%3A%2F%2Fexample.com/Example", UriParser::OrgProtocol).unwrap();
+    assert_eq!("store-link", uri.subcommand);
+    assert_eq!(Some("https://example.com"), uri.param("url"));
+    assert_eq!(Some("Example"), uri.param("title"));
+}
+
+#[test]
+fn org_protocol_decodes_double_encoded_values() {
+    let uri = ParsedUri::parse_as("org-protocol://store-link:/https%253A%252F%252Fexample.com/A%2520title", UriParser::OrgProtocol)
+        .unwrap();
+    assert_eq!(Some("https://example.com"), uri.param("url"));
+    assert_eq!(Some("A title"), uri.param("title"));
+}
+
+#[test]
+fn org_protocol_decodes_literal_percents_once() {
+    let uri = ParsedUri::parse_as("org-protocol://store-link:/https%3A%2F%2Fexample.com/Code%20%2541%20100%25", UriParser::OrgProtocol)
+        .unwrap();
+    assert_eq!(Some("https://example.com"), uri.param("url"));
+    assert_eq!(Some("Code %41 100%"), uri.param("title"));
+}
+
+#[test]
+fn org_protocol_keeps_slashes_in_the_last_value() {
+    let uri = ParsedUri::parse_as("org-protocol://capture:/w/https%3A%2F%2Fexample.com/Example/a/b", UriParser::OrgProtocol).unwrap();
+    assert_eq!(Some("a/b"), uri.param("body"));
+}
+
+#[test]
+fn org_protocol_parses_the_new_form() {
+    let new = "org-protocol://capture?template=w&url=https%3A%2F%2Fexample.com&title=Example&body=Some+text";
+    let uri = ParsedUri::parse_as(new, UriParser::OrgProtocol).unwrap();
+    assert_eq!(ParsedUri::parse(new).unwrap(), uri);
+
+    let uri = ParsedUri::parse_as("org-protocol:///capture?url=https%3A%2F%2Fexample.com", UriParser::OrgProtocol).unwrap();
+    assert_eq!("capture", uri.subcommand);
+    assert_eq!("", uri.path);
+    assert_eq!(Some("https://example.com"), uri.param("url"));
+}
+
+#[test]
+fn mailto_fields() {
+    let uri = ParsedUri::parse_as(
+        "mailto:a@example.com,b@example.com?Subject=1+1%3D2&body=Hi%20there&cc=c@example.com&to=d@example.com",
+        UriParser::Mailto,
+    )
+    .unwrap();
+    assert_eq!("mailto", uri.scheme);
+    assert_eq!(Some("a@example.com,b@example.com,d@example.com"), uri.param("to"));
+    assert_eq!(Some("1+1=2"), uri.param("subject"));
+    assert_eq!(Some("Hi there"), uri.param("body"));
+    assert_eq!(Some("c@example.com"), uri.param("cc"));
+    assert_eq!(1, uri.query.iter().filter(|p| p.name == "to").count());
+}
+
+#[test]
+fn magnet_fields() {
+    let uri = ParsedUri::parse_as(
+        "magnet:?xt=urn:btih:c12fe1c06bba254a9dc9f519b335aa7c1367a88a&dn=Some+file&tr=udp%3A%2F%2Fa.example%3A80&tr=udp%3A%2F%2Fb.example%3A80",
+        UriParser::Magnet,
+    )
+    .unwrap();
+    assert_eq!(Some("c12fe1c06bba254a9dc9f519b335aa7c1367a88a"), uri.param("hash"));
+    assert_eq!(Some("Some file"), uri.param("name"));
+    assert_eq!(Some("udp://a.example:80,udp://b.example:80"), uri.param("trackers"));
+    assert_eq!(Some("udp://a.example:80"), uri.param("tr"));
+}
+
+#[test]
+fn ssh_fields() {
+    let uri = ParsedUri::parse_as("ssh://git;fingerprint=ssh-rsa-AB@example.com:2222/srv/repo", UriParser::Ssh).unwrap();
+    assert_eq!("example.com", uri.subcommand);
+    assert_eq!("/srv/repo", uri.path);
+    assert_eq!(Some("git"), uri.param("user"));
+    assert_eq!(Some("example.com"), uri.param("host"));
+    assert_eq!(Some("2222"), uri.param("port"));
+    assert_eq!(Some("ssh-rsa-AB"), uri.param("fingerprint"));
+
+    let uri = ParsedUri::parse_as("ssh://[::1]", UriParser::Ssh).unwrap();
+    assert_eq!(Some("::1"), uri.param("host"));
+    assert_eq!(Some(""), uri.param("port"));
+    assert_eq!(Some(""), uri.param("user"));
+}
+
+#[test]
+fn tel_fields() {
+    let uri = ParsedUri::parse_as("tel:+1-201-555-0123;ext=1234", UriParser::Tel).unwrap();
+    assert_eq!("+1-201-555-0123", uri.path);
+    assert_eq!(Some("+1-201-555-0123"), uri.param("number"));
+    assert_eq!(Some("+12015550123"), uri.param("digits"));
+    assert_eq!(Some("1234"), uri.param("ext"));
+}
+
+#[test]
+fn file_fields() {
+    let uri = ParsedUri::parse_as("file:///home/me/My%20notes.md", UriParser::File).unwrap();
+    assert_eq!("", uri.subcommand);
+    assert_eq!("/home/me/My notes.md", uri.path);
+
+    let uri = ParsedUri::parse_as("file://server/share/a.txt", UriParser::File).unwrap();
+    assert_eq!("server", uri.subcommand);
+    assert_eq!(Some("/share/a.txt"), uri.param("path"));
+
+    assert_eq!("C:/Users/me", ParsedUri::parse_as("file:///C:/Users/me", UriParser::File).unwrap().path);
+    assert_eq!("/tmp/a", ParsedUri::parse_as("file:/tmp/a", UriParser::File).unwrap().path);
+}
@@ -0,0 +1,379 @@
+//! Parsing of the URIs sent to protoHandler.
+//!
+//! A URI is split into the components described in the `cli` module:
+//!
+//! ```text
+//! URI = proto :// subcommand [path] ? payload [# fragment]
+//! payload = name=val&name=val
+//! ```
+//!
+//! The `parser` of a protocol can split other URIs, such as `org-protocol` or
+//! `mailto:` URIs, into the same components, see `ParsedUri::parse_as`.
+
+use std::fmt;
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use regex::Regex;
+use serde::Serialize;
+
+use crate::config::UriParser;
+use crate::error::ProtoHandlerError;
+
+/// The characters that are percent-encoded in a URI component, everything but
+/// the unreserved characters of RFC 3986
+const COMPONENT : &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+/// A single `name=value` pair of the URI payload
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct QueryParam {
+    /// Decoded name of the parameter
+    pub name : String,
+    /// Decoded value of the parameter
+    pub value : String,
+}
+
+/// The components of a URI handled by protoHandler
+#[derive(Debug, Clone, Default, Serialize, PartialEq)]
+pub struct ParsedUri {
+    /// The protocol scheme, e.g. `snip-proto`
+    pub scheme : String,
+    /// The first component after `://`, e.g. `capture`
+    pub subcommand : String,
+    /// Anything after the subcommand and before the payload, decoded
+    pub path : String,
+    /// The decoded payload parameters, in the order they were given
+    pub query : Vec<QueryParam>,
+    /// The decoded fragment, if any
+    pub fragment : Option<String>,
+}
+
+impl ParsedUri {
+    /// Parses the given URI into its components.
+    ///
+    /// # Arguments
+    ///
+    /// * `uri` - A string slice representing the URI.
+    ///
+    /// # Returns
+    ///
+    /// * `Ok(ParsedUri)` - If the URI could be parsed.
+    /// * `Err(ProtoHandlerError)` - If the URI does not start with a protocol.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the URI does not contain a
+    /// recognized protocol.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the URI regular expression is invalid.
+    pub fn parse(uri : &str) -> Result<Self, ProtoHandlerError> {
+        let re = Regex::new(
+            r"^(?<proto>[a-z][a-zA-Z0-9-_]+)://(?<sub>[^/?#]*)(?<path>[^?#]*)(?:\?(?<query>[^#]*))?(?:#(?<fragment>.*))?$",
+        )
+        .unwrap();
+
+        let Some(c) = re.captures(uri) else {
+            return Err(ProtoHandlerError::UriParseError { uri : uri.to_string() });
+        };
+
+        let query = c.name("query").map(|q| parse_query(q.as_str(), decode_query)).unwrap_or_default();
+
+        Ok(Self {
+            scheme : c["proto"].to_string(),
+            subcommand : decode(&c["sub"]),
+            path : decode(&c["path"]),
+            query,
+            fragment : c.name("fragment").map(|f| decode(f.as_str())),
+        })
+    }
+
+    /// Parses the given URI into its components, with the given parser.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the URI does not contain a
+    /// recognized protocol.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use protohandlers::config::UriParser;
+    /// use protohandlers::ParsedUri;
+    ///
+    /// let uri = "org-protocol://capture:/t/https%3A%2F%2Fexample.com/Example/Some%20text";
+    /// let parsed = ParsedUri::parse_as(uri, UriParser::OrgProtocol).unwrap();
+    /// assert_eq!("capture", parsed.subcommand);
+    /// assert_eq!(Some("t"), parsed.param("template"));
+    /// assert_eq!(Some("https://example.com"), parsed.param("url"));
+    /// assert_eq!(Some("Some text"), parsed.param("body"));
+    /// ```
+    pub fn parse_as(uri : &str, parser : UriParser) -> Result<Self, ProtoHandlerError> {
+        match parser {
+            UriParser::Standard => Self::parse(uri),
+            UriParser::OrgProtocol => Self::parse_org_protocol(uri),
+            UriParser::Mailto => Self::parse_mailto(uri),
+            UriParser::Magnet => Self::parse_magnet(uri),
+            UriParser::Ssh => Self::parse_ssh(uri),
+            UriParser::Tel => Self::parse_tel(uri),
+            UriParser::File => Self::parse_file(uri),
+        }
+    }
+
+    /// Parses an `org-protocol` URI.
+    ///
+    /// The old positional form `proto://subcommand:/a/b/c` is turned into the
+    /// named parameters of the new form, which is parsed as usual.
+    fn parse_org_protocol(uri : &str) -> Result<Self, ProtoHandlerError> {
+        let mut parsed = Self::parse(uri)?;
+        if parsed.subcommand.is_empty() {
+            // org-protocol:///capture?url=... as written by some browsers
+            let path = parsed.path.trim_start_matches('/').to_string();
+            let (subcommand, rest) = path.split_once('/').unwrap_or((&path, ""));
+            parsed.subcommand = subcommand.to_string();
+            parsed.path = if rest.is_empty() { String::new() } else { format!("/{rest}") };
+        }
+        let Some(subcommand) = parsed.subcommand.strip_suffix(':').map(ToString::to_string) else {
+            return Ok(parsed);
+        };
+
+        // The positional values are split before decoding, as they contain
+        // encoded slashes
+        let after_scheme = &uri[uri.find("://").map_or(0, |i| i + 3)..];
+        let raw = after_scheme.split_once(':').map_or("", |(_, rest)| rest);
+        let raw = raw.split(['?', '#']).next().unwrap_or_default().trim_start_matches('/');
+        let names : &[&str] = match subcommand.as_str() {
+            "capture" => &["template", "url", "title", "body"],
+            "store-link" => &["url", "title"],
+            "open-source" => &["url"],
+            _ => &["url", "title", "body"],
+        };
+        // Slashes past the last name belong to its value
+        let values = raw.splitn(names.len(), '/').filter(|_| !raw.is_empty());
+        let double = is_double_encoded(raw);
+        let positional = names.iter().zip(values).map(|(name, value)| QueryParam {
+            name : (*name).to_string(),
+            value : if double { decode(&decode(value)) } else { decode(value) },
+        });
+
+        parsed.query = positional.chain(parsed.query).collect();
+        parsed.subcommand = subcommand;
+        parsed.path = String::new();
+        Ok(parsed)
+    }
+
+    /// Parses a `mailto:to,to?subject=..&body=..` URI.
+    ///
+    /// The recipients before the `?` and those of the `to` fields are joined
+    /// in a single `to` field.  A `+` is not a space in these URIs.
+    fn parse_mailto(uri : &str) -> Result<Self, ProtoHandlerError> {
+        let (scheme, rest) = split_scheme(uri)?;
+        let (recipients, query, fragment) = split_query(rest);
+        let fields : Vec<QueryParam> = parse_query(query.unwrap_or_default(), decode)
+            .into_iter()
+            .map(|p| QueryParam { name : p.name.to_ascii_lowercase(), value : p.value })
+            .collect();
+        let to : Vec<String> = std::iter::once(decode(recipients))
+            .chain(fields.iter().filter(|p| p.name == "to").map(|p| p.value.clone()))
+            .flat_map(|list| list.split(',').map(|r| r.trim().to_string()).collect::<Vec<String>>())
+            .filter(|r| !r.is_empty())
+            .collect();
+        let to = QueryParam { name : String::from("to"), value : to.join(",") };
+        Ok(Self {
+            scheme,
+            subcommand : String::new(),
+            path : decode(recipients),
+            query : std::iter::once(to).chain(fields.into_iter().filter(|p| p.name != "to")).collect(),
+            fragment,
+        })
+    }
+
+    /// Parses a `magnet:?xt=urn:btih:..&dn=..&tr=..` link.
+    ///
+    /// The `hash` field is the last component of the first `xt`, `name` is the
+    /// `dn` and `trackers` the `tr` fields joined with commas.
+    fn parse_magnet(uri : &str) -> Result<Self, ProtoHandlerError> {
+        let (scheme, rest) = split_scheme(uri)?;
+        let (path, query, fragment) = split_query(rest);
+        let mut query = parse_query(query.unwrap_or_default(), decode_query);
+        let values = |name : &str| -> Vec<String> {
+            query.iter().filter(|p| p.name == name).map(|p| p.value.clone()).collect()
+        };
+        let hash = values("xt").first().and_then(|xt| xt.rsplit(':').next()).unwrap_or_default().to_string();
+        let name = values("dn").first().cloned().unwrap_or_default();
+        let trackers = values("tr").join(",");
+        for (name, value) in [("hash", hash), ("name", name), ("trackers", trackers)] {
+            query.push(QueryParam { name : name.to_string(), value });
+        }
+        Ok(Self { scheme, subcommand : String::new(), path : decode(path), query, fragment })
+    }
+
+    /// Parses a `ssh://user;fingerprint=..@host:port/path` URI.
+    ///
+    /// The host is the subcommand.  The `port` field is empty when the URI
+    /// does not give one.
+    fn parse_ssh(uri : &str) -> Result<Self, ProtoHandlerError> {
+        let (scheme, rest) = split_scheme(uri)?;
+        let (rest, query, fragment) = split_query(rest);
+        let rest = rest.strip_prefix("//").unwrap_or(rest);
+        let (authority, path) = rest.find('/').map_or((rest, ""), |i| rest.split_at(i));
+        let (userinfo, server) = authority.rsplit_once('@').map_or(("", authority), |(u, s)| (u, s));
+        let (user, fingerprint) = userinfo.split_once(";fingerprint=").unwrap_or((userinfo, ""));
+        let (host, port) = match server.strip_prefix('[').and_then(|s| s.split_once(']')) {
+            Some((host, port)) => (host, port.trim_start_matches(':')),
+            None => server.split_once(':').unwrap_or((server, "")),
+        };
+        let host = decode(host);
+        let fields = [("user", decode(user)), ("host", host.clone()), ("port", decode(port)), ("fingerprint", decode(fingerprint))];
+        let fields = fields.into_iter().map(|(name, value)| QueryParam { name : name.to_string(), value });
+        Ok(Self {
+            scheme,
+            subcommand : host,
+            path : decode(path),
+            query : fields.chain(parse_query(query.unwrap_or_default(), decode_query)).collect(),
+            fragment,
+        })
+    }
+
+    /// Parses a `tel:+1-201-555-0123;ext=1234` URI.
+    ///
+    /// The `number` field is the number as given and `digits` the number
+    /// without its visual separators.  The parameters after `;`, such as
+    /// `ext`, are fields as well.
+    fn parse_tel(uri : &str) -> Result<Self, ProtoHandlerError> {
+        let (scheme, rest) = split_scheme(uri)?;
+        let mut parts = rest.split(';');
+        let number = decode(parts.next().unwrap_or_default());
+        let digits : String = number.chars().filter(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '*' | '#')).collect();
+        let params = parts.filter(|p| !p.is_empty()).map(|p| {
+            let (name, value) = p.split_once('=').unwrap_or((p, ""));
+            QueryParam { name : decode(name).to_ascii_lowercase(), value : decode(value) }
+        });
+        let fields = [("number", number.clone()), ("digits", digits)];
+        let fields = fields.into_iter().map(|(name, value)| QueryParam { name : name.to_string(), value });
+        Ok(Self {
+            scheme,
+            subcommand : String::new(),
+            path : number,
+            query : fields.chain(params).collect(),
+            fragment : None,
+        })
+    }
+
+    /// Parses a `file:///path`, `file://host/path` or `file:/path` URI.
+    ///
+    /// The host is the subcommand.  The leading `/` of a Windows path, as in
+    /// `file:///C:/Users`, is removed.
+    fn parse_file(uri : &str) -> Result<Self, ProtoHandlerError> {
+        let (scheme, rest) = split_scheme(uri)?;
+        let (rest, query, fragment) = split_query(rest);
+        let (host, path) = match rest.strip_prefix("//") {
+            Some(rest) => rest.find('/').map_or((rest, ""), |i| rest.split_at(i)),
+            None => ("", rest),
+        };
+        let host = decode(host);
+        let mut path = decode(path);
+        let bytes = path.as_bytes();
+        if bytes.len() >= 3 && bytes[0] == b'/' && bytes[1].is_ascii_alphabetic() && bytes[2] == b':' {
+            path.remove(0);
+        }
+        let fields = [("host", host.clone()), ("path", path.clone())];
+        let fields = fields.into_iter().map(|(name, value)| QueryParam { name : name.to_string(), value });
+        Ok(Self {
+            scheme,
+            subcommand : host,
+            path,
+            query : fields.chain(parse_query(query.unwrap_or_default(), decode_query)).collect(),
+            fragment,
+        })
+    }
+
+    /// Returns the value of the first payload parameter with the given name.
+    #[must_use] pub fn param(&self, name : &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|p| p.name == name)
+            .map(|p| p.value.as_str())
+    }
+
+    /// Returns the payload encoded again as `name=val&name=val`.
+    #[must_use] pub fn query_string(&self) -> String {
+        self.query
+            .iter()
+            .map(|p| format!("{}={}", encode(&p.name), encode(&p.value)))
+            .collect::<Vec<String>>()
+            .join("&")
+    }
+}
+
+impl fmt::Display for ParsedUri {
+    /// Writes the URI, encoding its components again
+    fn fmt(&self, f : &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path : Vec<String> = self.path.split('/').map(encode).collect();
+        write!(f, "{}://{}{}", self.scheme, encode(&self.subcommand), path.join("/"))?;
+        if !self.query.is_empty() {
+            write!(f, "?{}", self.query_string())?;
+        }
+        if let Some(fragment) = &self.fragment {
+            write!(f, "#{}", encode(fragment))?;
+        }
+        Ok(())
+    }
+}
+
+/// Splits a URI into its scheme and the text after the `:`
+fn split_scheme(uri : &str) -> Result<(String, &str), ProtoHandlerError> {
+    let re = Regex::new(r"^(?<proto>[a-z][a-zA-Z0-9-_]+):").unwrap();
+    match re.find(uri) {
+        Some(m) => Ok((uri[..m.end() - 1].to_ascii_lowercase(), &uri[m.end()..])),
+        None => Err(ProtoHandlerError::UriParseError { uri : uri.to_string() }),
+    }
+}
+
+/// Splits the text after the scheme into the text before the query, the
+/// query and the decoded fragment
+fn split_query(rest : &str) -> (&str, Option<&str>, Option<String>) {
+    let (rest, fragment) = match rest.split_once('#') {
+        Some((rest, fragment)) => (rest, Some(decode(fragment))),
+        None => (rest, None),
+    };
+    match rest.split_once('?') {
+        Some((rest, query)) => (rest, Some(query), fragment),
+        None => (rest, None, fragment),
+    }
+}
+
+/// Parses the `name=val&name=val` payload, decoding the names and values
+fn parse_query(query : &str, decode : fn(&str) -> String) -> Vec<QueryParam> {
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            QueryParam { name : decode(name), value : decode(value) }
+        })
+        .collect()
+}
+
+/// Percent-encodes a component of a URI, such as a payload value
+#[must_use] pub fn encode(s : &str) -> String {
+    utf8_percent_encode(s, COMPONENT).to_string()
+}
+
+/// Percent-decodes a component of the URI
+fn decode(s : &str) -> String {
+    percent_decode_str(s).decode_utf8_lossy().into_owned()
+}
+
+/// Returns true if the positional values of an old `org-protocol` URI are
+/// encoded twice, as done by some bookmarklets: every escape is then an
+/// escaped `%`.  A value encoded once only has `%25` for a literal `%`.
+fn is_double_encoded(raw : &str) -> bool {
+    let mut escapes = raw.match_indices('%').map(|(i, _)| &raw[i + 1..]).peekable();
+    escapes.peek().is_some() && escapes.all(|rest| rest.starts_with("25"))
+}
+
+/// Percent-decodes a payload name or value, where `+` stands for a space
+fn decode_query(s : &str) -> String {
+    decode(&s.replace('+', " "))
+}